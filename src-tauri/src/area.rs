@@ -0,0 +1,262 @@
+use crate::flight_path::{get_coord_meters, NZTM, WGS84};
+use gdal::{vector::LayerAccess, Dataset};
+use geo::{Area, Centroid, Coord, Geometry, Intersects, Line, LineString, Polygon};
+use proj::Proj;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct AreaDiagnostics {
+    /// Whether the last coordinate repeats the first
+    pub is_closed: bool,
+    /// Whether the boundary is free of self-intersections
+    pub is_simple: bool,
+    pub vertex_count: usize,
+    /// Area in square kilometers, positive when the ring is counter-clockwise
+    pub signed_area: f64,
+    pub centroid: Option<[f64; 2]>,
+    /// Whether the boundary crosses ±180° longitude, which can't be planned
+    pub crosses_antimeridian: bool,
+}
+
+/// Units the area metrics are returned in. Areas are in the square of the unit.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum LengthUnits {
+    Meters,
+    Kilometers,
+    Feet,
+    Miles,
+}
+
+impl LengthUnits {
+    /// Length of one unit in meters
+    fn meters(self) -> f64 {
+        match self {
+            LengthUnits::Meters => 1.0,
+            LengthUnits::Kilometers => 1000.0,
+            LengthUnits::Feet => 0.3048,
+            LengthUnits::Miles => 1609.344,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AreaMetrics {
+    pub area: f64,
+    /// Length of the boundary, including the boundaries of any holes
+    pub perimeter: f64,
+    /// Centroid as WGS84 [lon, lat]
+    pub centroid: Option<[f64; 2]>,
+    /// Largest distance between any two vertices of the boundary
+    pub longest_dimension: f64,
+}
+
+/// Measures a WGS84 search area in NZTM for area reports, without planning anything.
+/// Lengths are returned in `units` and the area in square `units`.
+#[tauri::command]
+pub fn area_metrics(coords: Vec<[f64; 2]>, units: LengthUnits) -> Result<AreaMetrics, String> {
+    let points: Vec<Coord> = coords.iter().map(|c| Coord::from((c[0], c[1]))).collect();
+    let coords_meters =
+        get_coord_meters(&points.iter().collect::<Vec<_>>(), WGS84).map_err(|e| e.to_string())?;
+    let polygon_meters = Polygon::new(LineString::from(coords_meters), vec![]);
+
+    let perimeter: f64 = std::iter::once(polygon_meters.exterior())
+        .chain(polygon_meters.interiors())
+        .flat_map(|ring| ring.lines())
+        .map(|line| line.dx().hypot(line.dy()))
+        .sum();
+    let vertices = &polygon_meters.exterior().0;
+    let longest_dimension = vertices
+        .iter()
+        .enumerate()
+        .flat_map(|(i, a)| {
+            vertices[i + 1..].iter().map(move |b| {
+                let delta = *a - *b;
+                delta.x.hypot(delta.y)
+            })
+        })
+        .fold(0.0, f64::max);
+    let centroid = polygon_meters.centroid().and_then(|centroid| {
+        let to_wgs84 = Proj::new_known_crs(NZTM, WGS84, None).ok()?;
+        let (lon, lat) = to_wgs84.convert((centroid.x(), centroid.y())).ok()?;
+        Some([lon, lat])
+    });
+
+    let scale = units.meters();
+    Ok(AreaMetrics {
+        area: polygon_meters.unsigned_area() / scale.powi(2),
+        perimeter: perimeter / scale,
+        centroid,
+        longest_dimension: longest_dimension / scale,
+    })
+}
+
+/// Checks a drawn search area so the UI can warn the user before generating
+#[tauri::command]
+pub fn validate_area(coords: Vec<[f64; 2]>) -> Result<AreaDiagnostics, String> {
+    let is_closed = coords.len() > 1 && coords.first() == coords.last();
+    let vertex_count = if is_closed {
+        coords.len() - 1
+    } else {
+        coords.len()
+    };
+
+    let points: Vec<Coord> = coords.iter().map(|c| Coord::from((c[0], c[1]))).collect();
+    let polygon = Polygon::new(LineString::from(points), vec![]);
+
+    let coords_meters = get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>(), WGS84)
+        .map_err(|e| e.to_string())?;
+    let polygon_meters = Polygon::new(LineString::from(coords_meters), vec![]);
+
+    Ok(AreaDiagnostics {
+        is_closed,
+        is_simple: is_simple(polygon.exterior()),
+        vertex_count,
+        signed_area: polygon_meters.signed_area() / 1_000_000.0,
+        centroid: polygon.centroid().map(|c| [c.x(), c.y()]),
+        crosses_antimeridian: crosses_antimeridian(&coords),
+    })
+}
+
+/// Reads a search area from a polygon feature in a vector file such as a shapefile,
+/// returned as WGS84 [lon, lat] pairs. Takes the first feature in the layer unless
+/// `feature` is given.
+#[tauri::command]
+pub fn load_area_from_shapefile(
+    path: String,
+    layer: usize,
+    feature: Option<usize>,
+) -> Result<Vec<[f64; 2]>, String> {
+    let dataset = Dataset::open(&path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+    let mut layer = dataset
+        .layer(layer)
+        .map_err(|e| format!("Could not read layer {}: {}", layer, e))?;
+    // Assume WGS84 if the file doesn't say
+    let source_crs = layer.spatial_ref().and_then(|srs| srs.to_proj4().ok());
+
+    let feature_index = feature.unwrap_or(0);
+    let feature = layer
+        .features()
+        .nth(feature_index)
+        .ok_or_else(|| format!("Layer has no feature {}", feature_index))?;
+    let geometry = feature.geometry().ok_or("Feature has no geometry")?;
+    let polygon = match geometry.to_geo().map_err(|e| e.to_string())? {
+        Geometry::Polygon(polygon) => polygon,
+        Geometry::MultiPolygon(multi) if multi.0.len() == 1 => multi.0[0].clone(),
+        _ => {
+            return Err(format!(
+                "Feature {} is a {}, not a single polygon",
+                feature_index,
+                geometry.geometry_name()
+            ))
+        }
+    };
+
+    let ring = polygon.exterior().coords().map(|c| (c.x, c.y));
+    match source_crs {
+        Some(crs) => {
+            let to_wgs84 = Proj::new_known_crs(&crs, WGS84, None)
+                .map_err(|e| format!("Could not reproject the area: {}", e))?;
+            ring.map(|point| {
+                to_wgs84
+                    .convert(point)
+                    .map(|(lon, lat)| [lon, lat])
+                    .map_err(|e| format!("Could not reproject the area: {}", e))
+            })
+            .collect()
+        }
+        None => Ok(ring.map(|(lon, lat)| [lon, lat]).collect()),
+    }
+}
+
+/// Returns true if any edge of the WGS84 ring jumps more than 180° in longitude,
+/// which only happens when the shorter way round crosses the antimeridian
+pub(crate) fn crosses_antimeridian(coords: &[[f64; 2]]) -> bool {
+    // Pair each vertex with the next, wrapping round to close the ring
+    coords
+        .iter()
+        .zip(coords.iter().cycle().skip(1))
+        .any(|(a, b)| (a[0] - b[0]).abs() > 180.0)
+}
+
+/// Returns true if no two non-adjacent edges of the closed ring intersect. Repeated
+/// consecutive vertices are dropped first, as their zero-length edges would touch
+/// both neighbours.
+fn is_simple(ring: &LineString) -> bool {
+    let mut vertices = ring.0.clone();
+    vertices.dedup();
+    let edges: Vec<Line> = LineString::from(vertices).lines().collect();
+    let n = edges.len();
+    for i in 0..n {
+        for j in (i + 1)..n {
+            // Neighbouring edges always share a vertex
+            if j == i + 1 || (i == 0 && j == n - 1) {
+                continue;
+            }
+            if edges[i].intersects(&edges[j]) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// WGS84 ring through the NZTM points, given in meters from a point near Christchurch
+    fn wgs84_ring(points: &[(f64, f64)]) -> Vec<[f64; 2]> {
+        let to_wgs84 = Proj::new_known_crs(NZTM, WGS84, None).unwrap();
+        points
+            .iter()
+            .map(|&(x, y)| {
+                let (lon, lat) = to_wgs84
+                    .convert((1_570_000.0 + x, 5_180_000.0 + y))
+                    .unwrap();
+                [lon, lat]
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < expected * 1e-6,
+            "expected {} got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn square_metrics() {
+        let square = wgs84_ring(&[
+            (0.0, 0.0),
+            (1000.0, 0.0),
+            (1000.0, 1000.0),
+            (0.0, 1000.0),
+            (0.0, 0.0),
+        ]);
+        let metrics = area_metrics(square.clone(), LengthUnits::Kilometers).unwrap();
+        assert_close(metrics.area, 1.0);
+        assert_close(metrics.perimeter, 4.0);
+        assert_close(metrics.longest_dimension, 2_f64.sqrt());
+
+        let centre = wgs84_ring(&[(500.0, 500.0)])[0];
+        let centroid = metrics.centroid.unwrap();
+        assert!((centroid[0] - centre[0]).abs() < 1e-9 && (centroid[1] - centre[1]).abs() < 1e-9);
+
+        let metrics = area_metrics(square, LengthUnits::Meters).unwrap();
+        assert_close(metrics.area, 1_000_000.0);
+        assert_close(metrics.perimeter, 4000.0);
+    }
+
+    #[test]
+    fn right_triangle_metrics() {
+        let triangle = wgs84_ring(&[(0.0, 0.0), (300.0, 0.0), (0.0, 400.0), (0.0, 0.0)]);
+        let metrics = area_metrics(triangle, LengthUnits::Feet).unwrap();
+        assert_close(metrics.area, 60_000.0 / 0.3048_f64.powi(2));
+        assert_close(metrics.perimeter, 1200.0 / 0.3048);
+        assert_close(metrics.longest_dimension, 500.0 / 0.3048);
+    }
+}
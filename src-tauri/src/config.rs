@@ -0,0 +1,463 @@
+use crate::coverage::CoverageMask;
+use crate::elevation::{VerticalUnits, DEFAULT_BAND};
+use crate::export::OutputFormat;
+use crate::flight_path::{Pattern, WGS84};
+use crate::writer::{Compression, OutputPrecision};
+use serde::{Deserialize, Serialize};
+
+/// Mission level settings used when planning and writing the mission. Every field
+/// has a default so the frontend only needs to send the ones it changes.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MissionConfig {
+    pub pattern: Pattern,
+    /// CRS of the search area coordinates. Coordinates already in NZTM skip the
+    /// round trip through WGS84.
+    pub input_crs: String,
+    /// Camera zoom applied at the start of each pass
+    pub zoom: Option<f64>,
+    /// Distance in meters within which collinear waypoints are merged
+    pub merge_tolerance: Option<f64>,
+    pub precision: OutputPrecision,
+    pub compression: Compression,
+    pub fly_to_wayline_mode: FlyToWaylineMode,
+    pub exit_on_rc_lost: ExitOnRcLost,
+    pub rc_lost_action: RcLostAction,
+    pub height_mode: HeightMode,
+    /// Takeoff/home point as [lon, lat, altitude]
+    pub home_point: Option<[f64; 3]>,
+    /// Seconds to hover at each photo waypoint before taking the photo
+    pub hover_time: f64,
+    /// Fly the mission starting from the opposite end
+    pub reverse: bool,
+    /// Minimum height above the terrain in meters that any waypoint may be flown at
+    pub min_clearance_m: f64,
+    /// Maximum height above the terrain in meters that any waypoint may be flown
+    /// at, usually the legal ceiling. None disables the limit.
+    pub max_altitude_agl: Option<f64>,
+    /// Review formats written alongside the KMZ
+    pub export_formats: Vec<OutputFormat>,
+    /// Photo file suffix pattern, e.g. `SITE_A_{:04}`. Defaults to the bare index.
+    pub file_suffix_pattern: Option<String>,
+    pub turn_mode: TurnMode,
+    pub gimbal_pitch_mode: GimbalPitchMode,
+    /// Minimum radius in meters the aircraft can turn at. Only used with curved
+    /// turn modes, since the default mode stops at each waypoint and turns in place.
+    pub min_turn_radius_m: Option<f64>,
+    /// Write a separate mission file for each battery, when the drone endurance is known
+    pub split_by_battery: bool,
+    /// Keep counting photo file suffixes across split mission files instead of
+    /// restarting at 0 in each one
+    pub continuous_numbering: bool,
+    /// Wind used to estimate the ground speed of each leg
+    pub wind: Option<Wind>,
+    /// Maximum distance in meters any waypoint may be from the home point
+    pub max_range_m: Option<f64>,
+    /// Remove waypoints beyond the maximum range instead of only flagging them
+    pub clip_to_range: bool,
+    /// Band of the elevation raster to read heights from, starting at 1
+    pub elevation_band: usize,
+    /// Units of the elevation raster. Read from the raster metadata if not set.
+    pub vertical_units: Option<VerticalUnits>,
+    /// Planned capture time (RFC 3339) used to work out the sun position
+    pub capture_time: Option<String>,
+    /// Orient the lawnmower perpendicular to the sun instead of along the search area
+    pub sun_orientation: bool,
+    /// Distance in meters to extend each line past the boundary to turn in
+    pub turnaround_buffer_m: Option<f64>,
+    /// Shift every other line by half the photo spacing for a brick-laid grid
+    pub grid_offset: bool,
+    /// Slow down approaching the turns at the ends of each line
+    pub speed_ramp: Option<SpeedRamp>,
+    /// Altitudes in meters to cover the whole area at, flown in order. Line spacing
+    /// is worked out for each altitude. Empty covers it once at the drone altitude.
+    pub altitude_layers: Vec<f64>,
+    /// Gimbal mount the payload is on. Only the M300/M350 carry more than one, at
+    /// positions 0, 1 and 2.
+    pub payload_position: u8,
+    /// Add photos at the ends of each line until the footprint reaches the boundary
+    pub close_line_ends: bool,
+    /// Smallest distance in meters between photos along a line, however steep the
+    /// terrain gets
+    pub min_photo_spacing_m: Option<f64>,
+    /// Distance in meters to grow the search area by before the lines are laid out,
+    /// so the grid overhangs the boundary. Negative values shrink it instead.
+    pub boundary_buffer_m: Option<f64>,
+    /// Estimated number of waypoints above which the full generation only runs once
+    /// the user has confirmed it, since the slope adjustment is slow on large areas
+    pub confirm_above_waypoints: Option<usize>,
+    /// Give the KML export's folders and placemarks fixed ids so tools that reload it
+    /// can match up the features between generations
+    pub kml_stable_ids: bool,
+    /// Distance in meters between flight lines, instead of working it out from the
+    /// camera footprint and overlap
+    pub line_spacing_m: Option<f64>,
+    /// Distance in meters between photos along a line on flat ground, instead of
+    /// using the line spacing
+    pub waypoint_spacing_m: Option<f64>,
+    /// How the search area is made from the input coordinates
+    pub area_mode: AreaMode,
+    /// Ground station as [lon, lat] to check each waypoint can be seen from over the
+    /// terrain, for radio relay or observation
+    pub visibility_from: Option<[f64; 2]>,
+    /// Start each battery's mission after the first at the last waypoint flown on the
+    /// battery before, so a resumed mission carries on along the same line
+    pub resume_segments: bool,
+    /// Turn damping distance for curved turns, as a fraction of the distance to the
+    /// nearest neighbouring waypoint. 0.1 to 0.3 gives smooth turns. Values are
+    /// clamped to 0 to 0.5 so the curve never reaches halfway to the next waypoint.
+    pub turn_damping_fraction: Option<f64>,
+    /// Gimbal pitch in degrees for a second photo taken after the first one at each
+    /// capture waypoint, which is straight down unless the pattern sets another pitch,
+    /// for 3D work without separate oblique passes. Doubles the number of photos.
+    pub oblique_pitch: Option<f64>,
+    /// Overlap preset used for the line and photo spacing instead of the drone's
+    /// overlap. `line_spacing_m` and `waypoint_spacing_m` still take priority.
+    pub quality: Option<Quality>,
+    /// Write a GeoTIFF of the area the photos cover alongside the KMZ. Rasterizing
+    /// is slow for large areas at fine resolutions, so it is off by default.
+    pub coverage_mask: Option<CoverageMask>,
+    /// Boundary the operation is authorised within, in the input CRS. The search
+    /// area is clipped to it and any waypoint left outside it is removed.
+    pub keep_in: Option<Vec<[f64; 2]>>,
+    /// Point in the input CRS that a flight line, and on flat ground a photo, always
+    /// passes through, so repeat surveys photograph the same spots even if the area
+    /// is drawn slightly differently. The grid is centred on the area without one.
+    pub anchor: Option<[f64; 2]>,
+    /// Altitude in meters, in the height mode, for waypoints with no elevation data
+    /// under them, including every waypoint when the raster can't be opened. Defaults
+    /// to the drone altitude, or the layer altitude with altitude layers.
+    pub fallback_altitude: Option<f64>,
+    /// Write each flight line as its own wayline in the WPML, so single lines can be
+    /// restarted or re-flown, instead of one wayline for the whole mission
+    pub wayline_per_line: bool,
+    /// How photos are triggered
+    pub capture_mode: CaptureMode,
+    /// Number of waypoints at the start of each pass over which the gimbal pitch moves
+    /// evenly from the previous pass's pitch to the new one, so the first frames
+    /// aren't taken mid-snap. The pitch snaps at the first waypoint if not set.
+    pub gimbal_ramp_waypoints: Option<usize>,
+    /// Maximum total distance in meters the mission may fly. The waypoints are cut
+    /// off at the last one within the distance, rather than split like batteries.
+    pub max_total_distance_m: Option<f64>,
+    /// When the actions at each waypoint are triggered
+    pub action_trigger: ActionTrigger,
+    /// Height in meters of the geoid above the WGS84 ellipsoid over the area, which is
+    /// roughly 5 to 40 m across New Zealand. The elevation data is above mean sea
+    /// level, so this is added to it for WGS84 heights. Taken as 0 if not set.
+    pub geoid_offset_m: Option<f64>,
+}
+
+impl Default for MissionConfig {
+    fn default() -> Self {
+        MissionConfig {
+            pattern: Pattern::default(),
+            input_crs: WGS84.to_string(),
+            zoom: None,
+            merge_tolerance: None,
+            precision: OutputPrecision::default(),
+            compression: Compression::default(),
+            fly_to_wayline_mode: FlyToWaylineMode::default(),
+            exit_on_rc_lost: ExitOnRcLost::default(),
+            rc_lost_action: RcLostAction::default(),
+            height_mode: HeightMode::default(),
+            home_point: None,
+            hover_time: 0.0,
+            reverse: false,
+            min_clearance_m: 30.0,
+            max_altitude_agl: Some(120.0),
+            export_formats: Vec::new(),
+            file_suffix_pattern: None,
+            turn_mode: TurnMode::default(),
+            gimbal_pitch_mode: GimbalPitchMode::default(),
+            min_turn_radius_m: None,
+            split_by_battery: false,
+            continuous_numbering: false,
+            wind: None,
+            max_range_m: None,
+            clip_to_range: false,
+            elevation_band: DEFAULT_BAND,
+            vertical_units: None,
+            capture_time: None,
+            sun_orientation: false,
+            turnaround_buffer_m: None,
+            grid_offset: false,
+            speed_ramp: None,
+            altitude_layers: Vec::new(),
+            payload_position: 0,
+            close_line_ends: false,
+            min_photo_spacing_m: None,
+            boundary_buffer_m: None,
+            confirm_above_waypoints: None,
+            kml_stable_ids: false,
+            line_spacing_m: None,
+            waypoint_spacing_m: None,
+            area_mode: AreaMode::default(),
+            visibility_from: None,
+            resume_segments: false,
+            turn_damping_fraction: None,
+            oblique_pitch: None,
+            quality: None,
+            coverage_mask: None,
+            keep_in: None,
+            anchor: None,
+            fallback_altitude: None,
+            wayline_per_line: false,
+            capture_mode: CaptureMode::default(),
+            gimbal_ramp_waypoints: None,
+            max_total_distance_m: None,
+            action_trigger: ActionTrigger::default(),
+            geoid_offset_m: None,
+        }
+    }
+}
+
+/// How the input coordinates describe the search area
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AreaMode {
+    /// The coordinates are the boundary of the area, in order
+    #[default]
+    Polygon,
+    /// The coordinates are scattered points and the area is their convex hull
+    ConvexHull,
+}
+
+/// Reconstruction quality presets, for choosing the photo overlap without knowing
+/// what overlap a photogrammetry job needs
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Quality {
+    /// 70% front and 60% side overlap, enough for orthomosaics of flat open ground
+    Draft,
+    /// 80% front and 70% side overlap, the usual recommendation for 3D models
+    Standard,
+    /// 85% front and 75% side overlap, for vegetation, steep terrain and fine detail
+    High,
+}
+
+impl Quality {
+    /// Returns the front (along line) and side (between lines) overlap in percent
+    pub fn overlaps(&self) -> (f64, f64) {
+        match self {
+            Quality::Draft => (70.0, 60.0),
+            Quality::Standard => (80.0, 70.0),
+            Quality::High => (85.0, 75.0),
+        }
+    }
+}
+
+/// How the aircraft flies from takeoff to the first waypoint
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum FlyToWaylineMode {
+    /// Climb to the safe takeoff height first, then fly across
+    #[default]
+    Safely,
+    /// Fly straight to the first waypoint, for short missions near obstacles
+    PointToPoint,
+}
+
+impl FlyToWaylineMode {
+    pub fn as_wpml(&self) -> &'static str {
+        match self {
+            FlyToWaylineMode::Safely => "safely",
+            FlyToWaylineMode::PointToPoint => "pointToPoint",
+        }
+    }
+}
+
+/// Whether the mission continues when the RC signal is lost
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ExitOnRcLost {
+    GoContinue,
+    #[default]
+    ExecuteLostAction,
+}
+
+impl ExitOnRcLost {
+    pub fn as_wpml(&self) -> &'static str {
+        match self {
+            ExitOnRcLost::GoContinue => "goContinue",
+            ExitOnRcLost::ExecuteLostAction => "executeLostAction",
+        }
+    }
+}
+
+/// Action taken on RC loss when `ExitOnRcLost::ExecuteLostAction` is set
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum RcLostAction {
+    #[default]
+    GoBack,
+    Landing,
+    Hover,
+}
+
+impl RcLostAction {
+    pub fn as_wpml(&self) -> &'static str {
+        match self {
+            RcLostAction::GoBack => "goBack",
+            RcLostAction::Landing => "landing",
+            RcLostAction::Hover => "hover",
+        }
+    }
+}
+
+/// Reference the waypoint heights are measured from
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum HeightMode {
+    #[default]
+    #[serde(rename = "WGS84")]
+    Wgs84,
+    RelativeToStartPoint,
+}
+
+impl HeightMode {
+    pub fn as_wpml(&self) -> &'static str {
+        match self {
+            HeightMode::Wgs84 => "WGS84",
+            HeightMode::RelativeToStartPoint => "relativeToStartPoint",
+        }
+    }
+}
+
+/// How the aircraft turns at each waypoint
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TurnMode {
+    /// Stop at the waypoint and turn in place
+    #[default]
+    ToPointAndStopWithDiscontinuityCurvature,
+    /// Fly through the waypoint on a curved path
+    ToPointAndPassWithContinuityCurvature,
+    /// Turn early so the curve passes close to the waypoint
+    CoordinateTurn,
+}
+
+impl TurnMode {
+    pub fn as_wpml(&self) -> &'static str {
+        match self {
+            TurnMode::ToPointAndStopWithDiscontinuityCurvature => {
+                "toPointAndStopWithDiscontinuityCurvature"
+            }
+            TurnMode::ToPointAndPassWithContinuityCurvature => {
+                "toPointAndPassWithContinuityCurvature"
+            }
+            TurnMode::CoordinateTurn => "coordinateTurn",
+        }
+    }
+
+    /// Whether the aircraft keeps moving through turns, so is limited by its turn radius
+    pub fn is_curved(&self) -> bool {
+        *self != TurnMode::ToPointAndStopWithDiscontinuityCurvature
+    }
+}
+
+/// How photos are triggered along the mission
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CaptureMode {
+    /// One photo at each capture waypoint
+    #[default]
+    Photo,
+    /// Photos every `interval_s` seconds from each waypoint marked to start a
+    /// timelapse until the next one marked to stop it, or the end of the wayline,
+    /// instead of the single photos. Written as a DJI `multipleTiming` action
+    /// trigger, which Pilot 2 supports on the M30 series, the Mavic 3 Enterprise
+    /// series and the M300/M350 RTK. Other aircraft ignore the interval photos.
+    Timelapse { interval_s: f64 },
+}
+
+/// When the action group at each waypoint is triggered. These are the DJI WPML
+/// action trigger types that apply to a single waypoint's actions.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ActionTrigger {
+    /// Once on reaching the waypoint
+    #[default]
+    ReachPoint,
+    /// Spread evenly over the leg to the next waypoint. The last waypoint of each
+    /// wayline has no next waypoint, so its actions are triggered on reaching it.
+    BetweenAdjacentPoints,
+    /// A photo every `interval_s` seconds over each run of photo waypoints. The other
+    /// actions are triggered on reaching each waypoint and no oblique photo is taken.
+    MultipleTiming { interval_s: f64 },
+    /// A photo every `interval_m` meters over each run of photo waypoints. The other
+    /// actions are triggered on reaching each waypoint and no oblique photo is taken.
+    MultipleDistance { interval_m: f64 },
+}
+
+impl ActionTrigger {
+    pub fn as_wpml(&self) -> &'static str {
+        match self {
+            ActionTrigger::ReachPoint => "reachPoint",
+            ActionTrigger::BetweenAdjacentPoints => "betweenAdjacentPoints",
+            ActionTrigger::MultipleTiming { .. } => "multipleTiming",
+            ActionTrigger::MultipleDistance { .. } => "multipleDistance",
+        }
+    }
+
+    /// The `actionTriggerParam` to write with the trigger type, if it takes one
+    pub fn param(&self) -> Option<f64> {
+        match self {
+            ActionTrigger::MultipleTiming { interval_s } => Some(*interval_s),
+            ActionTrigger::MultipleDistance { interval_m } => Some(*interval_m),
+            ActionTrigger::ReachPoint | ActionTrigger::BetweenAdjacentPoints => None,
+        }
+    }
+}
+
+/// How the gimbal pitch is controlled between waypoints
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum GimbalPitchMode {
+    /// The pilot controls the pitch
+    Manual,
+    /// The pitch moves smoothly between each waypoint's gimbal pitch
+    #[default]
+    UsePointSetting,
+}
+
+impl GimbalPitchMode {
+    pub fn as_wpml(&self) -> &'static str {
+        match self {
+            GimbalPitchMode::Manual => "manual",
+            GimbalPitchMode::UsePointSetting => "usePointSetting",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SpeedRamp {
+    /// Distance in meters from the end of a line over which the speed ramps down
+    pub distance_m: f64,
+    /// Speed in m/s at the end of the line
+    pub min_speed: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Wind {
+    /// Wind speed in m/s
+    pub speed: f64,
+    /// Compass bearing in degrees the wind is blowing from
+    pub direction: f64,
+}
+
+impl Wind {
+    /// Returns the ground speed in m/s when flying at `airspeed` along a track with
+    /// the given compass bearing (radians). The aircraft crabs into the crosswind,
+    /// so only the remaining part of its airspeed moves it along the track.
+    pub fn ground_speed(&self, airspeed: f64, track: f64) -> f64 {
+        // Angle between the track and the direction the wind is blowing towards
+        let relative = track - (self.direction.to_radians() + std::f64::consts::PI);
+        let tailwind = self.speed * relative.cos();
+        let crosswind = self.speed * relative.sin();
+
+        let along_track = (airspeed.powi(2) - crosswind.powi(2)).max(0.0).sqrt();
+        // Never let a leg become impossible to fly in the estimate
+        (along_track + tailwind).max(0.1 * airspeed)
+    }
+}
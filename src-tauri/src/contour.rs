@@ -0,0 +1,306 @@
+use crate::config::MissionConfig;
+use crate::elevation::ElevationData;
+use crate::flight_path::{
+    adjust_waypoint_for_slope, calculate_gsd, calculate_slope_at_point, generate_coverage_rect,
+    get_coord_meters, get_ground_coverage, get_projection, Drone, FlightPathError, Waypoint,
+    NADIR_PITCH, NZTM, WGS84,
+};
+use gdal::Dataset;
+use geo::{
+    coordinate_position::CoordPos, BoundingRect, Coord, CoordinatePosition, LineString, Polygon,
+};
+use std::collections::{HashMap, VecDeque};
+
+/// Maximum number of elevation samples along each side of the contouring grid
+const MAX_GRID_SIZE: usize = 500;
+
+/// Identifies a grid edge by its lower corner and whether it runs along x
+type EdgeId = (usize, usize, bool);
+
+/// Elevation samples on a regular grid over the search area, in NZTM meters
+struct ElevationGrid {
+    origin: Coord,
+    cell: f64,
+    nx: usize,
+    ny: usize,
+    values: Vec<Option<f64>>,
+}
+
+impl ElevationGrid {
+    fn value(&self, i: usize, j: usize) -> Option<f64> {
+        self.values[j * self.nx + i]
+    }
+
+    fn corner(&self, i: usize, j: usize) -> Coord {
+        Coord {
+            x: self.origin.x + i as f64 * self.cell,
+            y: self.origin.y + j as f64 * self.cell,
+        }
+    }
+}
+
+/// Returns waypoints that follow the terrain contours across the search area, with
+/// photos every `spacing` meters along a flight line per contour at every `interval`
+/// meters of elevation. A contour that leaves the area and comes back is split into
+/// separate lines. Returns None if there is no elevation data over the area, and an
+/// error if the elevation raster is there but can't be read as configured.
+pub fn get_contour_waypoints(
+    polygon: &Polygon,
+    interval: f64,
+    spacing: f64,
+    vrt_path: &str,
+    drone: &Drone,
+    config: &MissionConfig,
+    warnings: &mut Vec<String>,
+) -> Result<Option<Vec<Waypoint>>, FlightPathError> {
+    let input_crs = config.input_crs.as_str();
+    // A missing raster means flat terrain, like the lawnmower
+    let Ok(dataset) = Dataset::open(vrt_path) else {
+        return Ok(None);
+    };
+    let elevation = ElevationData::new(&dataset, config.elevation_band, config.vertical_units)
+        .map_err(FlightPathError::Elevation)?;
+
+    let search_coords_meters =
+        get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>(), input_crs)?;
+    let search_polygon_meters = Polygon::new(LineString::from(search_coords_meters), vec![]);
+    let Some(bounds) = search_polygon_meters.bounding_rect() else {
+        return Ok(None);
+    };
+
+    // Sample at half the photo spacing, coarsening the grid for very large areas
+    let cell = (spacing / 2.0)
+        .max(bounds.width() / MAX_GRID_SIZE as f64)
+        .max(bounds.height() / MAX_GRID_SIZE as f64);
+    let nx = (bounds.width() / cell).ceil() as usize + 1;
+    let ny = (bounds.height() / cell).ceil() as usize + 1;
+    let mut grid = ElevationGrid {
+        origin: bounds.min(),
+        cell,
+        nx,
+        ny,
+        values: Vec::with_capacity(nx * ny),
+    };
+    for j in 0..ny {
+        for i in 0..nx {
+            let point = grid.corner(i, j);
+            grid.values.push(elevation.elevation_at(point.x, point.y));
+        }
+    }
+
+    let (min_elevation, max_elevation) = grid
+        .values
+        .iter()
+        .flatten()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &z| {
+            (min.min(z), max.max(z))
+        });
+    if !min_elevation.is_finite() {
+        return Ok(None);
+    }
+
+    let to_wgs84 = get_projection(NZTM, WGS84)?;
+    let footprint = get_ground_coverage(drone);
+    let mut waypoints = Vec::new();
+    let mut line_index = 0;
+    // Photos where the next contour is further away across the slope than a footprint
+    let mut gaps = 0;
+    let mut level = (min_elevation / interval).ceil() * interval;
+    while level <= max_elevation {
+        let resampled: Vec<Vec<Coord>> = trace_contours(&grid, level)
+            .iter()
+            .map(|contour| resample(contour, spacing))
+            .collect();
+        // Each stretch of a contour inside the area is flown as its own line
+        let area = &search_polygon_meters;
+        let lines = resampled.iter().flat_map(move |points| {
+            points
+                .split(move |p| area.coordinate_position(p) == CoordPos::Outside)
+                .filter(|line| !line.is_empty())
+        });
+        for points in lines {
+            let mut line_waypoints = Vec::new();
+            for (k, point) in points.iter().enumerate() {
+                // Orient the footprint across the direction of travel
+                let next = points.get(k + 1).or(points.get(k.wrapping_sub(1)));
+                let direction = next.map_or(0.0, |n| (n.y - point.y).atan2(n.x - point.x));
+                let perp_angle = direction + std::f64::consts::PI / 2.0;
+
+                let slope_angle = calculate_slope_at_point(*point, &elevation);
+                if slope_angle.tan() * footprint < interval {
+                    gaps += 1;
+                }
+                let coverage_rect =
+                    generate_coverage_rect(point, &slope_angle, &perp_angle, drone, &to_wgs84)?;
+                let adjusted_point = adjust_waypoint_for_slope(*point, &elevation, drone.altitude);
+
+                if let Ok((lon, lat)) = to_wgs84.convert((adjusted_point.x, adjusted_point.y)) {
+                    line_waypoints.push(Waypoint {
+                        coverage_rect,
+                        position: [lon, lat],
+                        bearing: 0.0,
+                        altitude: drone.altitude,
+                        line: line_index,
+                        zoom: None,
+                        gsd: calculate_gsd(drone, drone.altitude / slope_angle.cos().max(0.1)),
+                        terrain_elevation: elevation
+                            .elevation_at(adjusted_point.x, adjusted_point.y),
+                        capture: true,
+                        layer: 0,
+                        gimbal_pitch: NADIR_PITCH,
+                        speed: None,
+                        heading: None,
+                        timelapse: None,
+                    });
+                }
+            }
+
+            if !line_waypoints.is_empty() {
+                // Alternate direction between contours like the lawnmower lines
+                if line_index % 2 == 0 {
+                    waypoints.extend(line_waypoints);
+                } else {
+                    waypoints.extend(line_waypoints.into_iter().rev());
+                }
+                line_index += 1;
+            }
+        }
+        level += interval;
+    }
+
+    if gaps > 0 {
+        warnings.push(format!(
+            "The contours are more than a {:.0} m footprint apart at {} photos where the ground is flatter, leaving gaps between the lines. Use a smaller contour interval to cover them",
+            footprint, gaps
+        ));
+    }
+    Ok(Some(waypoints))
+}
+
+/// Traces the iso-elevation lines at `level` through the grid using marching squares
+fn trace_contours(grid: &ElevationGrid, level: f64) -> Vec<Vec<Coord>> {
+    let mut crossings: HashMap<EdgeId, Coord> = HashMap::new();
+    let mut segments: Vec<(EdgeId, EdgeId)> = Vec::new();
+
+    for j in 0..grid.ny - 1 {
+        for i in 0..grid.nx - 1 {
+            let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+            let values: Vec<f64> = corners
+                .iter()
+                .filter_map(|&(ci, cj)| grid.value(ci, cj))
+                .collect();
+            if values.len() < 4 {
+                continue;
+            }
+
+            // Edges in order bottom, right, top, left with the corners they join
+            let edges = [
+                ((i, j, true), 0, 1),
+                ((i + 1, j, false), 1, 2),
+                ((i, j + 1, true), 3, 2),
+                ((i, j, false), 0, 3),
+            ];
+            let mut crossed = Vec::new();
+            for (edge, a, b) in edges {
+                if (values[a] >= level) != (values[b] >= level) {
+                    let t = (level - values[a]) / (values[b] - values[a]);
+                    let start = grid.corner(corners[a].0, corners[a].1);
+                    let end = grid.corner(corners[b].0, corners[b].1);
+                    crossings.insert(edge, start + (end - start) * t);
+                    crossed.push(edge);
+                }
+            }
+
+            match crossed.len() {
+                2 => segments.push((crossed[0], crossed[1])),
+                4 => {
+                    // Saddle, resolved using the average of the corners as the centre
+                    let centre = values.iter().sum::<f64>() / 4.0;
+                    if (values[0] >= level) == (centre >= level) {
+                        segments.push((crossed[0], crossed[1]));
+                        segments.push((crossed[2], crossed[3]));
+                    } else {
+                        segments.push((crossed[0], crossed[3]));
+                        segments.push((crossed[1], crossed[2]));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    chain_segments(&segments)
+        .into_iter()
+        .map(|edges| edges.iter().map(|edge| crossings[edge]).collect())
+        .collect()
+}
+
+/// Joins segments that share an edge crossing into continuous polylines
+fn chain_segments(segments: &[(EdgeId, EdgeId)]) -> Vec<VecDeque<EdgeId>> {
+    let mut by_edge: HashMap<EdgeId, Vec<usize>> = HashMap::new();
+    for (k, (a, b)) in segments.iter().enumerate() {
+        by_edge.entry(*a).or_default().push(k);
+        by_edge.entry(*b).or_default().push(k);
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut chains = Vec::new();
+    for start in 0..segments.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        let mut chain = VecDeque::from([segments[start].0, segments[start].1]);
+
+        // Extend forwards from the tail, then backwards from the head
+        for forwards in [true, false] {
+            loop {
+                let end = if forwards {
+                    *chain.back().unwrap()
+                } else {
+                    *chain.front().unwrap()
+                };
+                let next = by_edge[&end].iter().copied().find(|&k| !used[k]);
+                let Some(k) = next else {
+                    break;
+                };
+                used[k] = true;
+                let (a, b) = segments[k];
+                let other = if a == end { b } else { a };
+                if forwards {
+                    chain.push_back(other);
+                } else {
+                    chain.push_front(other);
+                }
+            }
+        }
+        chains.push(chain);
+    }
+    chains
+}
+
+/// Returns points spaced `spacing` meters apart along the polyline, or just the first
+/// point if the spacing isn't a positive number
+pub(crate) fn resample(polyline: &[Coord], spacing: f64) -> Vec<Coord> {
+    let mut points = Vec::new();
+    let Some(first) = polyline.first() else {
+        return points;
+    };
+    points.push(*first);
+    if !spacing.is_finite() || spacing <= 0.0 {
+        return points;
+    }
+
+    let mut distance_to_next = spacing;
+    for pair in polyline.windows(2) {
+        let delta = pair[1] - pair[0];
+        let length = (delta.x.powi(2) + delta.y.powi(2)).sqrt();
+        let mut position = distance_to_next;
+        while position <= length {
+            points.push(pair[0] + delta * (position / length));
+            position += spacing;
+        }
+        distance_to_next = position - length;
+    }
+    points
+}
@@ -0,0 +1,439 @@
+use crate::config::Quality;
+use crate::export::create_parent_dir;
+use crate::flight_path::{
+    check_positive, get_coord_meters, get_projection, project_coords, Drone, FlightPathError,
+    Waypoint, NZTM, WGS84,
+};
+use crate::writer::absolute_path;
+use gdal::{raster::Buffer, spatial_ref::SpatialRef, DriverManager};
+use geo::{Area, BooleanOps, BoundingRect, Contains, Coord, LineString, Polygon, Rect};
+use proj::Proj;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Default heatmap cell size in meters
+const HEATMAP_CELL_SIZE: f64 = 5.0;
+/// Maximum number of heatmap cells along each side of the grid
+const MAX_HEATMAP_SIZE: usize = 400;
+/// Maximum number of coverage mask pixels along each side of the raster
+const MAX_MASK_SIZE: usize = 10_000;
+/// Where the coverage mask is written unless another path is given
+const COVERAGE_MASK_PATH: &str = "../output/coverage_mask.tif";
+
+/// Achieved overlap along one axis of the survey, in percent
+#[derive(Serialize, Deserialize, Default)]
+pub struct AxisOverlap {
+    pub min: f64,
+    pub mean: f64,
+    /// Waypoint indices of the pair with the lowest overlap
+    pub worst: Option<(usize, usize)>,
+    /// Waypoint index pairs whose overlap is below the requested overlap
+    pub below_target: Vec<(usize, usize)>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OverlapReport {
+    /// Overlap between consecutive photos on the same line
+    pub front: AxisOverlap,
+    /// Overlap between photos on neighbouring lines
+    pub side: AxisOverlap,
+}
+
+/// Number of photos covering each cell of a grid over the search area
+#[derive(Serialize, Deserialize)]
+pub struct CoverageHeatmap {
+    /// Cell size in meters
+    pub cell_size: f64,
+    /// South west and north east corners of the grid as [lon, lat]
+    pub bounds: [[f64; 2]; 2],
+    /// Photo counts by row from north to south, None for cells outside the search area
+    pub counts: Vec<Vec<Option<u32>>>,
+}
+
+/// Settings for the GeoTIFF of the area covered by the photos
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CoverageMask {
+    /// Pixel size in meters
+    pub resolution_m: f64,
+    /// Where to write the GeoTIFF, `../output/coverage_mask.tif` if not given
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+#[tauri::command]
+pub fn overlap_report(
+    waypoints: Vec<Waypoint>,
+    drone: Drone,
+    quality: Option<Quality>,
+) -> Result<OverlapReport, String> {
+    verify_overlap(&waypoints, &drone, quality).map_err(|e| e.to_string())
+}
+
+/// Counts the photos covering each cell of a grid over the search area, so gaps and
+/// wasted over-coverage can be seen at a glance. `cell_size` is in meters.
+#[tauri::command]
+pub fn coverage_heatmap(
+    coords: Vec<[f64; 2]>,
+    waypoints: Vec<Waypoint>,
+    cell_size: Option<f64>,
+) -> Result<CoverageHeatmap, String> {
+    let cell_size = check_positive("Heatmap cell size", cell_size.unwrap_or(HEATMAP_CELL_SIZE))
+        .map_err(|e| e.to_string())?;
+    let points: Vec<Coord> = coords.iter().map(|c| Coord::from((c[0], c[1]))).collect();
+    let coords_meters =
+        get_coord_meters(&points.iter().collect::<Vec<_>>(), WGS84).map_err(|e| e.to_string())?;
+    let area = Polygon::new(LineString::from(coords_meters), vec![]);
+    let bounds = area
+        .bounding_rect()
+        .ok_or("The search area has no points")?;
+    let to_nztm = get_projection(WGS84, NZTM).map_err(|e| e.to_string())?;
+
+    // Coarsen the grid for very large areas
+    let cell_size = cell_size
+        .max(bounds.width() / MAX_HEATMAP_SIZE as f64)
+        .max(bounds.height() / MAX_HEATMAP_SIZE as f64);
+    let columns = (bounds.width() / cell_size).ceil() as usize;
+    let rows = (bounds.height() / cell_size).ceil() as usize;
+    let cell_centre = |row: usize, column: usize| Coord {
+        x: bounds.min().x + (column as f64 + 0.5) * cell_size,
+        y: bounds.max().y - (row as f64 + 0.5) * cell_size,
+    };
+
+    let mut counts: Vec<Vec<Option<u32>>> = (0..rows)
+        .map(|row| {
+            (0..columns)
+                .map(|column| area.contains(&cell_centre(row, column)).then_some(0))
+                .collect()
+        })
+        .collect();
+
+    let footprints = waypoints
+        .iter()
+        .filter(|w| w.capture)
+        .map(|w| get_footprint_meters(w, &to_nztm))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    for footprint in footprints {
+        let Some(rect) = footprint.bounding_rect() else {
+            continue;
+        };
+        // Only check the cells under the footprint's bounding box
+        let first_column = ((rect.min().x - bounds.min().x) / cell_size)
+            .floor()
+            .max(0.0) as usize;
+        let last_column =
+            (((rect.max().x - bounds.min().x) / cell_size).ceil() as usize).min(columns);
+        let first_row = ((bounds.max().y - rect.max().y) / cell_size)
+            .floor()
+            .max(0.0) as usize;
+        let last_row = (((bounds.max().y - rect.min().y) / cell_size).ceil() as usize).min(rows);
+        for (row, cells) in counts.iter_mut().enumerate().take(last_row).skip(first_row) {
+            for (column, cell) in cells
+                .iter_mut()
+                .enumerate()
+                .take(last_column)
+                .skip(first_column)
+            {
+                if let Some(count) = cell {
+                    if footprint.contains(&cell_centre(row, column)) {
+                        *count += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // The whole cells reach slightly past the east and south of the area
+    let to_wgs84 = get_projection(NZTM, WGS84).map_err(|e| e.to_string())?;
+    let south_west = to_wgs84
+        .convert((bounds.min().x, bounds.max().y - rows as f64 * cell_size))
+        .map_err(|e| e.to_string())?;
+    let north_east = to_wgs84
+        .convert((bounds.min().x + columns as f64 * cell_size, bounds.max().y))
+        .map_err(|e| e.to_string())?;
+
+    Ok(CoverageHeatmap {
+        cell_size,
+        bounds: [[south_west.0, south_west.1], [north_east.0, north_east.1]],
+        counts,
+    })
+}
+
+/// Rasterizes the union of the photo footprints into a single band NZTM GeoTIFF,
+/// 1 where at least one photo covers the pixel centre and 0 elsewhere. The raster
+/// is aligned to whole multiples of the resolution so masks from different plans
+/// line up. Returns the absolute path of the file written.
+pub fn write_coverage_mask(
+    waypoints: &[Waypoint],
+    mask: &CoverageMask,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let to_nztm = get_projection(WGS84, NZTM)?;
+    let footprints: Vec<Polygon> = waypoints
+        .iter()
+        .filter(|w| w.capture)
+        .map(|w| get_footprint_meters(w, &to_nztm))
+        .collect::<Result<_, _>>()?;
+    let bounds = footprints
+        .iter()
+        .filter_map(|footprint| footprint.bounding_rect())
+        .reduce(|a, b| {
+            Rect::new(
+                Coord {
+                    x: a.min().x.min(b.min().x),
+                    y: a.min().y.min(b.min().y),
+                },
+                Coord {
+                    x: a.max().x.max(b.max().x),
+                    y: a.max().y.max(b.max().y),
+                },
+            )
+        })
+        .ok_or("No photo footprints to rasterize")?;
+
+    let mut resolution = mask.resolution_m;
+    if resolution <= 0.0 {
+        return Err(format!(
+            "Coverage mask resolution must be positive, got {}",
+            resolution
+        )
+        .into());
+    }
+    let coarsest = (bounds.width().max(bounds.height()) / MAX_MASK_SIZE as f64).ceil();
+    if resolution < coarsest {
+        println!(
+            "Warning: coverage mask resolution coarsened from {} m to {} m to limit the raster size",
+            resolution, coarsest
+        );
+        resolution = coarsest;
+    }
+    let min_x = (bounds.min().x / resolution).floor() * resolution;
+    let max_y = (bounds.max().y / resolution).ceil() * resolution;
+    let columns = ((bounds.max().x - min_x) / resolution).ceil() as usize;
+    let rows = ((max_y - bounds.min().y) / resolution).ceil() as usize;
+
+    let mut data = vec![0u8; columns * rows];
+    for footprint in &footprints {
+        let Some(rect) = footprint.bounding_rect() else {
+            continue;
+        };
+        // Only check the pixels under the footprint's bounding box
+        let first_column = ((rect.min().x - min_x) / resolution).floor().max(0.0) as usize;
+        let last_column = (((rect.max().x - min_x) / resolution).ceil() as usize).min(columns);
+        let first_row = ((max_y - rect.max().y) / resolution).floor().max(0.0) as usize;
+        let last_row = (((max_y - rect.min().y) / resolution).ceil() as usize).min(rows);
+        for row in first_row..last_row {
+            for column in first_column..last_column {
+                let pixel = &mut data[row * columns + column];
+                let centre = Coord {
+                    x: min_x + (column as f64 + 0.5) * resolution,
+                    y: max_y - (row as f64 + 0.5) * resolution,
+                };
+                if *pixel == 0 && footprint.contains(&centre) {
+                    *pixel = 1;
+                }
+            }
+        }
+    }
+
+    let path = mask.path.as_deref().unwrap_or(COVERAGE_MASK_PATH);
+    create_parent_dir(path)?;
+    let driver = DriverManager::get_driver_by_name("GTiff")?;
+    let mut dataset = driver.create_with_band_type::<u8, _>(path, columns, rows, 1)?;
+    dataset.set_geo_transform(&[min_x, resolution, 0.0, max_y, 0.0, -resolution])?;
+    dataset.set_spatial_ref(&SpatialRef::from_definition(NZTM)?)?;
+    let mut band = dataset.rasterband(1)?;
+    let mut buffer = Buffer::new((columns, rows), data);
+    band.write((0, 0), (columns, rows), &mut buffer)?;
+
+    println!("Created coverage mask at: {}", path);
+    Ok(absolute_path(path))
+}
+
+/// Measures the overlap actually achieved between neighbouring coverage rectangles,
+/// so a plan can be audited before it is flown. Photos are judged against the
+/// overlap `get_line_spacing` plans with for `quality`
+pub fn verify_overlap(
+    waypoints: &[Waypoint],
+    drone: &Drone,
+    quality: Option<Quality>,
+) -> Result<OverlapReport, FlightPathError> {
+    let to_nztm = get_projection(WGS84, NZTM)?;
+    let footprints: Vec<Polygon> = waypoints
+        .iter()
+        .map(|w| get_footprint_meters(w, &to_nztm))
+        .collect::<Result<_, _>>()?;
+
+    let centres: Vec<Coord> = footprints.iter().map(footprint_centre).collect();
+
+    // Only the photos count, transit and turnaround waypoints take none
+    let mut lines: BTreeMap<(usize, usize), Vec<usize>> = BTreeMap::new();
+    for (i, waypoint) in waypoints.iter().enumerate().filter(|(_, w)| w.capture) {
+        lines
+            .entry((waypoint.layer, waypoint.line))
+            .or_default()
+            .push(i);
+    }
+
+    let mut front_pairs = Vec::new();
+    for photos in lines.values() {
+        front_pairs.extend(photos.windows(2).map(|pair| (pair[0], pair[1])));
+    }
+
+    // Pair every photo with the closest photo on the next line of the same layer
+    let mut side_pairs = Vec::new();
+    for (&(layer, line), photos) in &lines {
+        let Some(next_photos) = lines.get(&(layer, line + 1)) else {
+            continue;
+        };
+        for &i in photos {
+            let closest = next_photos
+                .iter()
+                .map(|&j| (j, distance(centres[i], centres[j])))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((j, _)) = closest {
+                side_pairs.push((i, j));
+            }
+        }
+    }
+
+    let (front_target, side_target) =
+        quality.map_or((drone.overlap, drone.overlap), |q| q.overlaps());
+
+    Ok(OverlapReport {
+        front: measure_axis(&footprints, &front_pairs, front_target),
+        side: measure_axis(&footprints, &side_pairs, side_target),
+    })
+}
+
+fn measure_axis(footprints: &[Polygon], pairs: &[(usize, usize)], target: f64) -> AxisOverlap {
+    if pairs.is_empty() {
+        return AxisOverlap::default();
+    }
+
+    let mut report = AxisOverlap {
+        min: f64::INFINITY,
+        ..Default::default()
+    };
+    let mut total = 0.0;
+    for &(a, b) in pairs {
+        let overlap = get_overlap_percent(&footprints[a], &footprints[b]);
+        total += overlap;
+        if overlap < report.min {
+            report.min = overlap;
+            report.worst = Some((a, b));
+        }
+        if overlap < target {
+            report.below_target.push((a, b));
+        }
+    }
+    report.mean = total / pairs.len() as f64;
+    report
+}
+
+/// Returns the shared area of two footprints as a percentage of the smaller one
+fn get_overlap_percent(a: &Polygon, b: &Polygon) -> f64 {
+    let smallest = a.unsigned_area().min(b.unsigned_area());
+    if smallest <= 0.0 {
+        return 0.0;
+    }
+    a.intersection(b).unsigned_area() / smallest * 100.0
+}
+
+/// Returns the coverage rectangle of the waypoint projected to meters with `to_nztm`
+fn get_footprint_meters(waypoint: &Waypoint, to_nztm: &Proj) -> Result<Polygon, FlightPathError> {
+    let coords: Vec<Coord> = waypoint
+        .coverage_rect
+        .coords
+        .iter()
+        .map(|c| Coord::from((c[0], c[1])))
+        .collect();
+    let coords_meters = project_coords(&coords.iter().collect::<Vec<_>>(), to_nztm)?;
+    Ok(Polygon::new(LineString::from(coords_meters), vec![]))
+}
+
+fn footprint_centre(footprint: &Polygon) -> Coord {
+    let corners = &footprint.exterior().0[..4];
+    let sum = corners
+        .iter()
+        .fold(Coord { x: 0.0, y: 0.0 }, |acc, c| acc + *c);
+    sum / 4.0
+}
+
+fn distance(a: Coord, b: Coord) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flight_path::{CoverageRect, NADIR_PITCH};
+
+    const ORIGIN: Coord = Coord {
+        x: 1_750_000.0,
+        y: 5_900_000.0,
+    };
+
+    /// WGS84 [lon, lat] of the point `x`, `y` meters east and north of ORIGIN
+    fn wgs84(x: f64, y: f64, to_wgs84: &Proj) -> [f64; 2] {
+        let (lon, lat) = to_wgs84.convert((ORIGIN.x + x, ORIGIN.y + y)).unwrap();
+        [lon, lat]
+    }
+
+    /// Photo waypoint whose footprint spans `min` to `max` in meters from ORIGIN
+    fn photo(min: (f64, f64), max: (f64, f64), to_wgs84: &Proj) -> Waypoint {
+        let corners = [
+            (min.0, max.1),
+            (min.0, min.1),
+            (max.0, min.1),
+            (max.0, max.1),
+            (min.0, max.1),
+        ];
+        let centre = wgs84((min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0, to_wgs84);
+        Waypoint {
+            coverage_rect: CoverageRect {
+                coords: corners.map(|(x, y)| wgs84(x, y, to_wgs84)),
+                center: centre,
+            },
+            position: centre,
+            bearing: 0.0,
+            altitude: 100.0,
+            line: 0,
+            zoom: None,
+            gsd: None,
+            terrain_elevation: None,
+            capture: true,
+            layer: 0,
+            gimbal_pitch: NADIR_PITCH,
+            speed: None,
+            heading: None,
+            timelapse: None,
+        }
+    }
+
+    #[test]
+    fn heatmap_counts_edge_cell_under_footprint() {
+        let to_wgs84 = get_projection(NZTM, WGS84).unwrap();
+        // 98 m wide, so the last column of 10 m cells only partly overlaps the area
+        let area: Vec<[f64; 2]> = [
+            (0.0, 0.0),
+            (98.0, 0.0),
+            (98.0, 98.0),
+            (0.0, 98.0),
+            (0.0, 0.0),
+        ]
+        .iter()
+        .map(|&(x, y)| wgs84(x, y, &to_wgs84))
+        .collect();
+        // A photo straddling the east edge of the area
+        let waypoints = vec![photo((93.0, -10.0), (108.0, 108.0), &to_wgs84)];
+
+        let heatmap = coverage_heatmap(area, waypoints, Some(10.0)).unwrap();
+        assert_eq!(heatmap.counts.len(), 10);
+        for row in &heatmap.counts {
+            assert_eq!(row.len(), 10);
+            assert_eq!(row[9], Some(1), "edge cell not covered");
+            assert_eq!(row[8], Some(0));
+            assert_eq!(row[0], Some(0));
+        }
+    }
+}
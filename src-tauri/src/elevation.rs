@@ -0,0 +1,217 @@
+use gdal::{raster::RasterBand, Dataset};
+use serde::{Deserialize, Serialize};
+
+/// Nodata value used by the NZ elevation rasters, for bands that don't set one
+const NODATA: f64 = -32767.0;
+/// Band the elevations are read from unless another is chosen
+pub const DEFAULT_BAND: usize = 1;
+
+/// Units the elevation raster stores heights in
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum VerticalUnits {
+    Meters,
+    Feet,
+}
+
+impl VerticalUnits {
+    /// Reads the units from the band's unit type metadata, if it is set
+    fn detect(rasterband: &RasterBand) -> Option<Self> {
+        match rasterband.unit().to_lowercase().as_str() {
+            "m" | "metre" | "metres" | "meter" | "meters" => Some(VerticalUnits::Meters),
+            "ft" | "foot" | "feet" | "us-ft" | "ft-us" => Some(VerticalUnits::Feet),
+            _ => None,
+        }
+    }
+
+    fn to_meters(self) -> f64 {
+        match self {
+            VerticalUnits::Meters => 1.0,
+            VerticalUnits::Feet => 0.3048,
+        }
+    }
+}
+
+/// Terrain heights for the slope and terrain calculations, so they don't depend on
+/// the heights being read from a GDAL raster
+pub trait ElevationSource {
+    /// Returns the elevation in meters at the given NZTM point, or None if there is
+    /// no data there
+    fn elevation_at(&self, x: f64, y: f64) -> Option<f64>;
+
+    /// Distance in meters between neighbouring elevation samples
+    fn pixel_size(&self) -> f64;
+}
+
+/// Elevation raster used for the slope and terrain calculations. All elevations
+/// returned are in meters, whatever units the raster stores them in.
+pub struct ElevationData<'a> {
+    rasterband: RasterBand<'a>,
+    geotransform: [f64; 6],
+    /// Maps NZTM coordinates back to pixel and line, in the same layout as the
+    /// geotransform
+    inverse_geotransform: [f64; 6],
+    raster_size: (usize, usize),
+    vertical_scale: f64,
+    nodata: f64,
+}
+
+impl<'a> ElevationData<'a> {
+    /// Reads the given elevation band (1-based) of the dataset. If `vertical_units`
+    /// isn't given the units are read from the band metadata, assuming meters if
+    /// they aren't set.
+    pub fn new(
+        dataset: &'a Dataset,
+        band: usize,
+        vertical_units: Option<VerticalUnits>,
+    ) -> Result<Self, String> {
+        check_band(dataset, band)?;
+        let rasterband = dataset
+            .rasterband(band)
+            .map_err(|e| format!("Could not read elevation band {}: {}", band, e))?;
+        let geotransform = dataset
+            .geo_transform()
+            .map_err(|e| format!("Elevation raster has no geotransform: {}", e))?;
+        let inverse_geotransform = invert_geotransform(&geotransform)
+            .ok_or("Elevation raster geotransform can't be inverted, its pixels have no area")?;
+
+        let vertical_units = vertical_units
+            .or_else(|| VerticalUnits::detect(&rasterband))
+            .unwrap_or_else(|| {
+                println!("Warning: elevation units could not be determined, assuming meters");
+                VerticalUnits::Meters
+            });
+
+        Ok(ElevationData {
+            nodata: rasterband.no_data_value().unwrap_or(NODATA),
+            rasterband,
+            geotransform,
+            inverse_geotransform,
+            raster_size: dataset.raster_size(),
+            vertical_scale: vertical_units.to_meters(),
+        })
+    }
+
+    /// Width of a raster pixel in meters, assuming square pixels
+    pub fn pixel_size(&self) -> f64 {
+        // The length of a pixel's top edge, which is rotated in rotated rasters
+        self.geotransform[1].hypot(self.geotransform[4])
+    }
+
+    /// Returns the elevation in meters at the given NZTM point, or None if the point is
+    /// outside the raster or has no data
+    pub fn elevation_at(&self, x: f64, y: f64) -> Option<f64> {
+        // NaN would be cast to pixel 0 and read a real elevation
+        if !x.is_finite() || !y.is_finite() {
+            return None;
+        }
+        let inverse = &self.inverse_geotransform;
+        let pixel_x = (inverse[0] + inverse[1] * x + inverse[2] * y).floor() as isize;
+        let pixel_y = (inverse[3] + inverse[4] * x + inverse[5] * y).floor() as isize;
+
+        if pixel_x < 0
+            || pixel_y < 0
+            || pixel_x >= self.raster_size.0 as isize
+            || pixel_y >= self.raster_size.1 as isize
+        {
+            return None;
+        }
+
+        let mut buffer = [0.0f32; 1];
+        match self.rasterband.read_into_slice::<f32>(
+            (pixel_x, pixel_y),
+            (1, 1),
+            (1, 1),
+            &mut buffer,
+            None,
+        ) {
+            Ok(_) => {
+                let elevation = buffer[0] as f64;
+                if (elevation - self.nodata).abs() < 0.1 {
+                    None
+                } else {
+                    Some(elevation * self.vertical_scale)
+                }
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+impl ElevationSource for ElevationData<'_> {
+    fn elevation_at(&self, x: f64, y: f64) -> Option<f64> {
+        ElevationData::elevation_at(self, x, y)
+    }
+
+    fn pixel_size(&self) -> f64 {
+        ElevationData::pixel_size(self)
+    }
+}
+
+/// Inverts a GDAL geotransform, including its rotation terms, so world coordinates
+/// can be mapped to pixel and line. Returns None if the pixels have no area.
+fn invert_geotransform(gt: &[f64; 6]) -> Option<[f64; 6]> {
+    let det = gt[1] * gt[5] - gt[2] * gt[4];
+    if det.abs() < f64::EPSILON {
+        return None;
+    }
+    Some([
+        (gt[2] * gt[3] - gt[0] * gt[5]) / det,
+        gt[5] / det,
+        -gt[2] / det,
+        (gt[0] * gt[4] - gt[1] * gt[3]) / det,
+        -gt[4] / det,
+        gt[1] / det,
+    ])
+}
+
+/// Checks that the 1-based elevation band exists in the dataset
+pub fn check_band(dataset: &Dataset, band: usize) -> Result<(), String> {
+    let band_count = dataset.raster_count();
+    if band == 0 || band > band_count {
+        return Err(format!(
+            "Elevation band {} doesn't exist, the raster has {} band(s)",
+            band, band_count
+        ));
+    }
+    Ok(())
+}
+
+/// Terrain that is a flat plane, `height + slope.0 * x + slope.1 * y`, so the slope
+/// calculations can be checked against the analytic slope without a raster.
+/// Points more than `extent` meters from the origin on either axis have no data.
+#[cfg(test)]
+pub(crate) struct SyntheticElevationSource {
+    pub height: f64,
+    /// Rise per meter east and north
+    pub slope: (f64, f64),
+    pub pixel_size: f64,
+    pub extent: f64,
+}
+
+#[cfg(test)]
+impl SyntheticElevationSource {
+    /// An unbounded plane with 1 meter pixels
+    pub fn plane(slope: (f64, f64)) -> Self {
+        SyntheticElevationSource {
+            height: 100.0,
+            slope,
+            pixel_size: 1.0,
+            extent: f64::INFINITY,
+        }
+    }
+}
+
+#[cfg(test)]
+impl ElevationSource for SyntheticElevationSource {
+    fn elevation_at(&self, x: f64, y: f64) -> Option<f64> {
+        if x.abs() > self.extent || y.abs() > self.extent {
+            return None;
+        }
+        Some(self.height + self.slope.0 * x + self.slope.1 * y)
+    }
+
+    fn pixel_size(&self) -> f64 {
+        self.pixel_size
+    }
+}
@@ -0,0 +1,452 @@
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    writer::Writer,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{fs, io::Cursor, path::Path};
+
+use crate::config::{HeightMode, MissionConfig};
+use crate::coverage::write_coverage_mask;
+use crate::flight_path::{
+    get_coord_meters, Drone, FlightPathError, FlightPlanResult, Waypoint, WGS84,
+};
+use crate::writer::{absolute_path, create_kmz};
+use geo::Coord;
+
+/// Formats a plan can be written in. Everything but the KMZ can be written
+/// alongside it for review.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Kml,
+    GeoJson,
+    Csv,
+    Kmz,
+    /// The whole FlightPlanResult, which can be read back in
+    Json,
+    /// The CSV with blank columns for the pilot to fill in the actual capture time
+    /// and notes at each waypoint, as a field log
+    FlightLog,
+}
+
+/// Writes an already generated plan to `path` in another format, without
+/// recomputing the waypoints. Returns the absolute path of the file written.
+#[tauri::command]
+pub async fn export_plan(
+    result: FlightPlanResult,
+    drone: Drone,
+    format: OutputFormat,
+    path: String,
+    config: Option<MissionConfig>,
+) -> Result<String, String> {
+    let config = config.unwrap_or_default();
+    let waypoints = &result.waypoints;
+    let written = match format {
+        OutputFormat::Kml => write_flightpath_kml(waypoints, &path, config.kml_stable_ids),
+        OutputFormat::GeoJson => write_flightpath_geojson(waypoints, &path),
+        OutputFormat::Csv => write_flightpath_csv(waypoints, &path, false),
+        OutputFormat::FlightLog => write_flightpath_csv(waypoints, &path, true),
+        OutputFormat::Kmz => {
+            create_kmz(waypoints, &result.heading_angle, &drone, &config, &path, 0)
+                .await
+                .map(|(path, _)| path)
+        }
+        OutputFormat::Json => write_flightpath_json(&result, &path),
+    };
+    written.map_err(|e| format!("Error exporting plan: {}", e))
+}
+
+/// Returns the plan as a GeoJSON FeatureCollection string that the map can add as a
+/// layer directly, with the same features as the GeoJSON export
+#[tauri::command]
+pub fn plan_as_geojson(result: FlightPlanResult) -> String {
+    flightpath_geojson(&result.waypoints).to_string()
+}
+
+/// Returns the plan's waypoints as local East-North-Up offsets in meters from the
+/// WGS84 `home` point, for feeding into flight simulators
+#[tauri::command]
+pub fn plan_as_enu(
+    result: FlightPlanResult,
+    home: [f64; 3],
+    config: Option<MissionConfig>,
+) -> Result<Vec<[f64; 3]>, String> {
+    let config = config.unwrap_or_default();
+    to_enu(&result.waypoints, home, &config).map_err(|e| e.to_string())
+}
+
+/// Writes every extra output format requested in the mission config and
+/// returns the paths of the files written
+pub async fn write_exports(waypoints: &[Waypoint], config: &MissionConfig) -> Vec<String> {
+    let mut paths = Vec::new();
+    for format in &config.export_formats {
+        let result = match format {
+            OutputFormat::Kml => {
+                write_flightpath_kml(waypoints, "../output/flightpath.kml", config.kml_stable_ids)
+            }
+            OutputFormat::GeoJson => {
+                write_flightpath_geojson(waypoints, "../output/flightpath.geojson")
+            }
+            OutputFormat::Csv => write_flightpath_csv(waypoints, "../output/flightpath.csv", false),
+            OutputFormat::FlightLog => {
+                write_flightpath_csv(waypoints, "../output/flight_log.csv", true)
+            }
+            // The KMZ is always written
+            OutputFormat::Kmz => continue,
+            // Needs the finished result, so it's written by generate_flightpath
+            OutputFormat::Json => continue,
+        };
+        match result {
+            Ok(path) => paths.push(path),
+            Err(e) => println!("Error writing export: {}", e),
+        }
+    }
+    if let Some(mask) = &config.coverage_mask {
+        match write_coverage_mask(waypoints, mask) {
+            Ok(path) => paths.push(path),
+            Err(e) => println!("Error writing coverage mask: {}", e),
+        }
+    }
+    paths
+}
+
+/// Splits the waypoints into the runs that make up each flight line
+fn get_flight_lines(waypoints: &[Waypoint]) -> Vec<&[Waypoint]> {
+    waypoints.chunk_by(|a, b| a.line == b.line).collect()
+}
+
+/// Writes the plan as a KML review document with separate folders for the
+/// waypoints, the flight lines and the photo coverage. With `stable_ids` the folders
+/// are wrapped in one plan folder and every folder and placemark has an id based on
+/// its index, so the same feature keeps its id when the plan is regenerated.
+pub fn write_flightpath_kml(
+    waypoints: &[Waypoint],
+    path: &str,
+    stable_ids: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    create_parent_dir(path)?;
+    fs::write(path, generate_flightpath_kml(waypoints, stable_ids)?)?;
+    println!("Created KML file at: {}", path);
+    Ok(absolute_path(path))
+}
+
+fn generate_flightpath_kml(
+    waypoints: &[Waypoint],
+    stable_ids: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+
+    // XML declaration
+    writer.write_event(Event::Decl(quick_xml::events::BytesDecl::new(
+        "1.0",
+        Some("UTF-8"),
+        None,
+    )))?;
+
+    // Root kml element
+    let mut kml_start = BytesStart::new("kml");
+    kml_start.push_attribute(("xmlns", "http://www.opengis.net/kml/2.2"));
+    writer.write_event(Event::Start(kml_start))?;
+    writer.write_event(Event::Start(BytesStart::new("Document")))?;
+    write_text_element(&mut writer, "name", "Flight plan")?;
+
+    write_flight_line_style(&mut writer)?;
+
+    // Semi-transparent fill for the photo footprints
+    let mut coverage_style = BytesStart::new("Style");
+    coverage_style.push_attribute(("id", "coverage"));
+    writer.write_event(Event::Start(coverage_style))?;
+    writer.write_event(Event::Start(BytesStart::new("LineStyle")))?;
+    write_text_element(&mut writer, "color", "80ffffff")?;
+    write_text_element(&mut writer, "width", "1")?;
+    writer.write_event(Event::End(BytesEnd::new("LineStyle")))?;
+    writer.write_event(Event::Start(BytesStart::new("PolyStyle")))?;
+    write_text_element(&mut writer, "color", "4000ff00")?;
+    writer.write_event(Event::End(BytesEnd::new("PolyStyle")))?;
+    writer.write_event(Event::End(BytesEnd::new("Style")))?;
+
+    let id = |id: String| stable_ids.then_some(id);
+    if stable_ids {
+        write_start_with_id(&mut writer, "Folder", Some("flightPlan".into()))?;
+        write_text_element(&mut writer, "name", "Flight plan")?;
+    }
+
+    write_waypoints_folder(&mut writer, waypoints, stable_ids)?;
+
+    // One LineString per flight line
+    write_start_with_id(&mut writer, "Folder", id("lines".into()))?;
+    write_text_element(&mut writer, "name", "Flight lines")?;
+    for line in get_flight_lines(waypoints) {
+        write_line_placemark(
+            &mut writer,
+            &format!("Line {}", line[0].line),
+            line,
+            id(format!("line-{}", line[0].line)),
+        )?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("Folder")))?;
+
+    // Photo footprints
+    write_start_with_id(&mut writer, "Folder", id("footprints".into()))?;
+    write_text_element(&mut writer, "name", "Coverage")?;
+    for (i, waypoint) in waypoints.iter().enumerate() {
+        write_start_with_id(&mut writer, "Placemark", id(format!("footprint-{}", i)))?;
+        write_text_element(&mut writer, "name", &i.to_string())?;
+        write_text_element(&mut writer, "styleUrl", "#coverage")?;
+        writer.write_event(Event::Start(BytesStart::new("Polygon")))?;
+        writer.write_event(Event::Start(BytesStart::new("outerBoundaryIs")))?;
+        writer.write_event(Event::Start(BytesStart::new("LinearRing")))?;
+        let coords = waypoint
+            .coverage_rect
+            .coords
+            .iter()
+            .map(|c| format!("{},{}", c[0], c[1]))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write_text_element(&mut writer, "coordinates", &coords)?;
+        writer.write_event(Event::End(BytesEnd::new("LinearRing")))?;
+        writer.write_event(Event::End(BytesEnd::new("outerBoundaryIs")))?;
+        writer.write_event(Event::End(BytesEnd::new("Polygon")))?;
+        writer.write_event(Event::End(BytesEnd::new("Placemark")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("Folder")))?;
+    if stable_ids {
+        writer.write_event(Event::End(BytesEnd::new("Folder")))?;
+    }
+
+    // Close document and kml
+    writer.write_event(Event::End(BytesEnd::new("Document")))?;
+    writer.write_event(Event::End(BytesEnd::new("kml")))?;
+
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// Writes the solid line style used for the flight path, referenced as `#flightLine`
+pub(crate) fn write_flight_line_style(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut line_style = BytesStart::new("Style");
+    line_style.push_attribute(("id", "flightLine"));
+    writer.write_event(Event::Start(line_style))?;
+    writer.write_event(Event::Start(BytesStart::new("LineStyle")))?;
+    write_text_element(writer, "color", "ff0000ff")?;
+    write_text_element(writer, "width", "2")?;
+    writer.write_event(Event::End(BytesEnd::new("LineStyle")))?;
+    writer.write_event(Event::End(BytesEnd::new("Style")))?;
+    Ok(())
+}
+
+/// Writes a folder with a point placemark for each waypoint, named by its index.
+/// With `ids` the folder and placemarks are given ids from the waypoint index.
+pub(crate) fn write_waypoints_folder(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    waypoints: &[Waypoint],
+    ids: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_start_with_id(writer, "Folder", ids.then(|| "waypoints".into()))?;
+    write_text_element(writer, "name", "Waypoints")?;
+    for (i, waypoint) in waypoints.iter().enumerate() {
+        write_start_with_id(writer, "Placemark", ids.then(|| format!("waypoint-{}", i)))?;
+        write_text_element(writer, "name", &i.to_string())?;
+        writer.write_event(Event::Start(BytesStart::new("Point")))?;
+        let coords = format!("{},{}", waypoint.position[0], waypoint.position[1]);
+        write_text_element(writer, "coordinates", &coords)?;
+        writer.write_event(Event::End(BytesEnd::new("Point")))?;
+        writer.write_event(Event::End(BytesEnd::new("Placemark")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("Folder")))?;
+    Ok(())
+}
+
+/// Writes a placemark with a LineString joining the waypoints in order
+pub(crate) fn write_line_placemark(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    name: &str,
+    waypoints: &[Waypoint],
+    id: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_start_with_id(writer, "Placemark", id)?;
+    write_text_element(writer, "name", name)?;
+    write_text_element(writer, "styleUrl", "#flightLine")?;
+    writer.write_event(Event::Start(BytesStart::new("LineString")))?;
+    let coords = waypoints
+        .iter()
+        .map(|w| format!("{},{}", w.position[0], w.position[1]))
+        .collect::<Vec<_>>()
+        .join(" ");
+    write_text_element(writer, "coordinates", &coords)?;
+    writer.write_event(Event::End(BytesEnd::new("LineString")))?;
+    writer.write_event(Event::End(BytesEnd::new("Placemark")))?;
+    Ok(())
+}
+
+/// Writes the start tag of an element, with an `id` attribute if one is given
+fn write_start_with_id(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    name: &str,
+    id: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut start = BytesStart::new(name);
+    if let Some(id) = &id {
+        start.push_attribute(("id", id.as_str()));
+    }
+    writer.write_event(Event::Start(start))?;
+    Ok(())
+}
+
+/// Writes the plan as a GeoJSON FeatureCollection with the same layers as the KML
+pub fn write_flightpath_geojson(
+    waypoints: &[Waypoint],
+    path: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    create_parent_dir(path)?;
+    fs::write(
+        path,
+        serde_json::to_string_pretty(&flightpath_geojson(waypoints))?,
+    )?;
+    println!("Created GeoJSON file at: {}", path);
+    Ok(absolute_path(path))
+}
+
+/// Builds a FeatureCollection of the waypoints, flight lines and coverage polygons.
+/// Each feature has a `layer` property so they can be styled separately.
+fn flightpath_geojson(waypoints: &[Waypoint]) -> Value {
+    let mut features = Vec::new();
+
+    for (i, waypoint) in waypoints.iter().enumerate() {
+        features.push(json!({
+            "type": "Feature",
+            "properties": {
+                "layer": "waypoints",
+                "index": i,
+                "altitude": waypoint.altitude,
+                "bearing": waypoint.bearing,
+            },
+            "geometry": { "type": "Point", "coordinates": waypoint.position },
+        }));
+    }
+
+    for line in get_flight_lines(waypoints) {
+        let coords: Vec<[f64; 2]> = line.iter().map(|w| w.position).collect();
+        features.push(json!({
+            "type": "Feature",
+            "properties": { "layer": "flight_lines", "line": line[0].line },
+            "geometry": { "type": "LineString", "coordinates": coords },
+        }));
+    }
+
+    for (i, waypoint) in waypoints.iter().enumerate() {
+        features.push(json!({
+            "type": "Feature",
+            "properties": { "layer": "coverage", "index": i },
+            "geometry": { "type": "Polygon", "coordinates": [waypoint.coverage_rect.coords] },
+        }));
+    }
+
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+/// Writes the full plan as pretty-printed JSON
+pub fn write_flightpath_json(
+    result: &FlightPlanResult,
+    path: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    create_parent_dir(path)?;
+    serde_json::to_writer_pretty(fs::File::create(path)?, result)?;
+    println!("Created JSON file at: {}", path);
+    Ok(absolute_path(path))
+}
+
+/// Writes the waypoints as a CSV table with one row per waypoint. With `log_columns`
+/// blank actual capture time and notes columns are added, for use as a field log.
+pub fn write_flightpath_csv(
+    waypoints: &[Waypoint],
+    path: &str,
+    log_columns: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    create_parent_dir(path)?;
+    // Empty columns are left after each row of the flight log to be filled in
+    let log_suffix = if log_columns { ",," } else { "" };
+    let mut csv =
+        String::from("index,line,longitude,latitude,altitude,capture,gsd,terrain_elevation");
+    if log_columns {
+        csv.push_str(",actual_capture_time,notes");
+    }
+    csv.push('\n');
+    for (i, waypoint) in waypoints.iter().enumerate() {
+        let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{}{}\n",
+            i,
+            waypoint.line,
+            waypoint.position[0],
+            waypoint.position[1],
+            waypoint.altitude,
+            waypoint.capture,
+            optional(waypoint.gsd),
+            optional(waypoint.terrain_elevation),
+            log_suffix,
+        ));
+    }
+    fs::write(path, csv)?;
+    println!("Created CSV file at: {}", path);
+    Ok(absolute_path(path))
+}
+
+/// Converts the waypoints to `[east, north, up]` offsets in meters from `home`, the
+/// takeoff point as WGS84 `[longitude, latitude, altitude]` with the altitude above
+/// mean sea level. East and north are along the NZTM grid axes, which are within a
+/// few degrees of true east and north over New Zealand. Up is the height above
+/// takeoff: relative altitudes already are, and WGS84 altitudes are brought down to
+/// mean sea level with the configured geoid offset before the home altitude is
+/// taken off.
+pub fn to_enu(
+    waypoints: &[Waypoint],
+    home: [f64; 3],
+    config: &MissionConfig,
+) -> Result<Vec<[f64; 3]>, FlightPathError> {
+    let mut coords = vec![Coord::from((home[0], home[1]))];
+    coords.extend(
+        waypoints
+            .iter()
+            .map(|w| Coord::from((w.position[0], w.position[1]))),
+    );
+    let coords_meters = get_coord_meters(&coords.iter().collect::<Vec<_>>(), WGS84)?;
+
+    let home_meters = coords_meters[0];
+    let up_offset = match config.height_mode {
+        HeightMode::Wgs84 => config.geoid_offset_m.unwrap_or(0.0) + home[2],
+        HeightMode::RelativeToStartPoint => 0.0,
+    };
+    Ok(coords_meters[1..]
+        .iter()
+        .zip(waypoints)
+        .map(|(c, w)| {
+            [
+                c.x - home_meters.x,
+                c.y - home_meters.y,
+                w.altitude - up_offset,
+            ]
+        })
+        .collect())
+}
+
+/// Creates the directory a file is about to be written into
+pub(crate) fn create_parent_dir(path: &str) -> std::io::Result<()> {
+    match Path::new(path).parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn write_text_element(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    name: &str,
+    text: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    writer.write_event(Event::Start(BytesStart::new(name)))?;
+    writer.write_event(Event::Text(BytesText::new(text)))?;
+    writer.write_event(Event::End(BytesEnd::new(name)))?;
+    Ok(())
+}
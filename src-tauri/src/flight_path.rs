@@ -1,21 +1,102 @@
-use crate::writer::write_wqml;
+use crate::area::crosses_antimeridian;
+use crate::config::{AreaMode, HeightMode, MissionConfig, Quality, SpeedRamp};
+use crate::contour::get_contour_waypoints;
+use crate::elevation::{check_band, ElevationData, ElevationSource, DEFAULT_BAND};
+use crate::export::{write_exports, write_flightpath_json, OutputFormat};
+use crate::perimeter::get_perimeter_waypoints;
+use crate::profile::known_model_limits;
+use crate::sun::{get_sun_hint, SunHint};
+use crate::writer::{write_battery_kmzs, write_wqml};
+use chrono::{DateTime, Utc};
 use gdal::Dataset;
-use geo::Area;
 use geo::{
     algorithm::MinimumRotatedRect, coordinate_position::CoordPos, Coord, CoordinatePosition,
     LineString, Polygon,
 };
+use geo::{
+    Area, BooleanOps, BoundingRect, Buffer, Centroid, ConvexHull, MultiLineString, MultiPoint,
+};
 use nalgebra::{Vector2, Vector3};
-use proj::Proj;
+use proj::{Proj, ProjError};
 use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize)]
+use std::{collections::HashSet, fmt, ops::Range, sync::Once};
+
+/// CRS of the coordinates sent to and returned from the frontend
+pub(crate) const WGS84: &str = "EPSG:4326";
+/// Projected CRS that all the flight path math is done in
+pub(crate) const NZTM: &str = "EPSG:2193";
+/// Elevation raster used for the slope adjustment
+const VRT_PATH: &str = "../data/elevation.vrt";
+/// Mean radius of the earth in meters, for great-circle distances
+const EARTH_RADIUS: f64 = 6_371_008.8;
+/// Height in meters of the ground station antenna or observer above the terrain
+const STATION_HEIGHT: f64 = 2.0;
+/// Most terrain samples taken along each sight line
+const MAX_SIGHT_SAMPLES: usize = 2000;
+/// Distance in meters within which consecutive search area vertices are duplicates
+const DUPLICATE_VERTEX_TOLERANCE: f64 = 0.01;
+/// Steps per photo spacing that positions along a slope-adjusted line are snapped to
+const LINE_STEPS_PER_PHOTO: usize = 20;
+/// Gimbal pitch in degrees that points the camera straight down
+pub(crate) const NADIR_PITCH: f64 = -90.0;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Drone {
     pub model: String,
     pub fov: f64,
     pub altitude: f64,
     pub overlap: f64,
     pub speed: f64,
+    /// Camera focal length in millimeters
+    #[serde(default)]
+    pub focal_length: Option<f64>,
+    /// Sensor pixel pitch in micrometers
+    #[serde(default)]
+    pub pixel_pitch: Option<f64>,
+    /// Usable flight time of one battery in minutes
+    #[serde(default)]
+    pub endurance: Option<f64>,
+    /// Fastest speed in m/s the aircraft flies a waypoint mission at
+    #[serde(default)]
+    pub max_auto_speed: Option<f64>,
+    /// Highest altitude in meters above sea level the aircraft can fly at
+    #[serde(default)]
+    pub max_altitude: Option<f64>,
+    /// Shortest time in seconds the camera needs between photos
+    #[serde(default)]
+    pub min_shutter_interval_s: Option<f64>,
+}
+
+impl Drone {
+    /// Highest payload position index the aircraft has a gimbal mount for. The
+    /// M300/M350 take up to three payloads, everything else a single one.
+    pub fn max_payload_position(&self) -> u8 {
+        let model = self.model.to_uppercase();
+        if model.contains("M300") || model.contains("M350") {
+            2
+        } else {
+            0
+        }
+    }
+
+    /// Returns the fastest mission speed in m/s, from the profile or otherwise the
+    /// known limit for the model
+    pub fn speed_limit(&self) -> Option<f64> {
+        self.max_auto_speed
+            .or_else(|| self.known_limits().map(|(speed, _)| speed))
+    }
+
+    /// Returns the altitude ceiling in meters, from the profile or otherwise the
+    /// known limit for the model
+    pub fn altitude_limit(&self) -> Option<f64> {
+        self.max_altitude
+            .or_else(|| self.known_limits().map(|(_, altitude)| altitude))
+    }
+
+    /// Waypoint speed limit in m/s and service ceiling in meters for the model
+    fn known_limits(&self) -> Option<(f64, f64)> {
+        known_model_limits(&self.model)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
@@ -24,154 +105,1965 @@ pub struct CoverageRect {
     pub center: [f64; 2],
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct FlightPlanResult {
     pub waypoints: Vec<Waypoint>,
     pub heading_angle: f64,
     pub search_area: f64,
     pub est_flight_time: f64,
+    pub min_gsd: Option<f64>,
+    pub max_gsd: Option<f64>,
+    /// Number of waypoints removed by merging collinear runs
+    pub merged_waypoints: usize,
+    /// Indices of waypoints raised to keep the minimum ground clearance
+    pub clearance_adjustments: Vec<usize>,
+    /// Indices of waypoints lowered to stay under the maximum altitude
+    pub ceiling_adjustments: Vec<usize>,
+    pub warnings: Vec<String>,
+    /// Number of batteries needed to fly the whole mission, if the endurance is known
+    pub batteries_required: Option<usize>,
+    /// Indices of waypoints further from the home point than the maximum range.
+    /// If clipping is enabled these are the indices before they were removed.
+    pub out_of_range: Vec<usize>,
+    /// Absolute paths of the files written for this plan
+    pub output_files: Vec<String>,
+    /// Sun position and suggested orientation, if a capture time was given
+    pub sun_hint: Option<SunHint>,
+    /// Absolute path of the manifest listing the per-battery mission files, if the
+    /// mission was split
+    pub battery_manifest: Option<String>,
+    /// Whether generation stopped at the estimate because the mission is larger than
+    /// the confirmation limit. Generate again with `confirmed` set to run it.
+    #[serde(default)]
+    pub confirmation_required: bool,
+    /// Number of waypoints written to the mission KMZ, to check against `waypoints`
+    #[serde(default)]
+    pub kmz_waypoints: Option<usize>,
+    /// Indices of waypoints hidden by the terrain from the visibility ground station
+    #[serde(default)]
+    pub hidden_waypoints: Vec<usize>,
+    /// Approximate area in square kilometers left unflown because the mission was cut
+    /// off at the maximum total distance
+    #[serde(default)]
+    pub truncated_area: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct Waypoint {
     pub coverage_rect: CoverageRect,
     pub position: [f64; 2],
+    /// Heading of the aircraft and gimbal yaw, in degrees
     pub bearing: f64,
     pub altitude: f64,
+    /// Index of the flight line (pass) this waypoint belongs to
+    #[serde(default)]
+    pub line: usize,
+    /// Camera zoom ratio to set when arriving at this waypoint
+    #[serde(default)]
+    pub zoom: Option<f64>,
+    /// Ground sample distance in cm/pixel, if the camera parameters are known
+    #[serde(default)]
+    pub gsd: Option<f64>,
+    /// Terrain elevation directly below the waypoint, if elevation data is available
+    #[serde(default)]
+    pub terrain_elevation: Option<f64>,
+    /// Whether a photo is taken at this waypoint. False for transit waypoints.
+    #[serde(default = "default_capture")]
+    pub capture: bool,
+    /// Index of the altitude layer this waypoint belongs to
+    #[serde(default)]
+    pub layer: usize,
+    /// Gimbal pitch in degrees set when arriving at this waypoint, -90 is straight down
+    /// and the default for survey photos
+    #[serde(default = "default_gimbal_pitch")]
+    pub gimbal_pitch: f64,
+    /// Speed in m/s to fly on from this waypoint at, instead of the drone speed
+    #[serde(default)]
+    pub speed: Option<f64>,
+    /// Compass heading in degrees to turn the aircraft to at this waypoint, instead
+    /// of the mission heading
+    #[serde(default)]
+    pub heading: Option<f64>,
+    /// Starts or stops interval photos at this waypoint in timelapse capture mode
+    #[serde(default)]
+    pub timelapse: Option<TimelapseMark>,
+}
+
+/// Where a run of interval photos starts or stops
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TimelapseMark {
+    Start,
+    Stop,
+}
+
+fn default_capture() -> bool {
+    true
+}
+
+fn default_gimbal_pitch() -> f64 {
+    NADIR_PITCH
+}
+
+/// Reasons a flight plan can't be computed for the search area
+#[derive(Debug)]
+pub enum FlightPathError {
+    /// A coordinate is NaN or infinite, such as from an unfinished drag in the map
+    NonFiniteCoordinate { index: usize },
+    /// The search area crosses the antimeridian
+    CrossesAntimeridian,
+    /// The drone has no gimbal mount at the configured payload position
+    PayloadPosition {
+        position: u8,
+        model: String,
+        max: u8,
+    },
+    /// The elevation raster can't be read as configured
+    Elevation(String),
+    /// A speed is faster than the drone can fly a mission at
+    SpeedLimit { speed: f64, max: f64, model: String },
+    /// A waypoint is above the drone's altitude ceiling
+    AltitudeLimit {
+        altitude: f64,
+        max: f64,
+        model: String,
+    },
+    /// The points given for a convex hull area don't enclose any area
+    DegenerateHull,
+    /// Shrinking the search area by the boundary buffer leaves nothing of it
+    EmptyArea,
+    /// No waypoints fall inside the search area
+    NoWaypoints,
+    /// The search area doesn't overlap the keep-in boundary
+    OutsideKeepIn,
+    /// PROJ couldn't create a projection or convert a coordinate
+    Projection(String),
+    /// A distance or interval setting is zero, negative or not a number, which would
+    /// leave the line generation stepping forever
+    InvalidSetting { setting: &'static str, value: f64 },
+}
+
+impl fmt::Display for FlightPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlightPathError::CrossesAntimeridian => {
+                write!(f, "Search area crosses the antimeridian, which is not supported")
+            }
+            FlightPathError::PayloadPosition {
+                position,
+                model,
+                max,
+            } => write!(
+                f,
+                "Payload position {} is not available on the {}, which supports positions 0 to {}",
+                position, model, max
+            ),
+            FlightPathError::SpeedLimit { speed, max, model } => write!(
+                f,
+                "A speed of {:.1} m/s is faster than the {} flies missions at, the limit is {:.1} m/s",
+                speed, model, max
+            ),
+            FlightPathError::AltitudeLimit {
+                altitude,
+                max,
+                model,
+            } => write!(
+                f,
+                "A waypoint at {:.0} m is above the {}'s ceiling of {:.0} m",
+                altitude, model, max
+            ),
+            FlightPathError::Elevation(e) => write!(f, "{}", e),
+            FlightPathError::DegenerateHull => write!(
+                f,
+                "A convex hull search area needs at least three points that aren't in a line"
+            ),
+            FlightPathError::EmptyArea => {
+                write!(f, "The boundary buffer leaves none of the search area")
+            }
+            FlightPathError::NonFiniteCoordinate { index } => write!(
+                f,
+                "Coordinate {} of the search area is not a finite number, redraw the area",
+                index
+            ),
+            FlightPathError::OutsideKeepIn => {
+                write!(f, "The search area is entirely outside the keep-in boundary")
+            }
+            FlightPathError::NoWaypoints => write!(
+                f,
+                "No waypoints fall inside the search area. Try a lower altitude or overlap, or a larger area"
+            ),
+            FlightPathError::Projection(e) => write!(f, "Coordinate projection failed: {}", e),
+            FlightPathError::InvalidSetting { setting, value } => {
+                write!(f, "{} must be a positive number, got {}", setting, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlightPathError {}
+
+impl From<ProjError> for FlightPathError {
+    fn from(e: ProjError) -> Self {
+        FlightPathError::Projection(e.to_string())
+    }
+}
+
+/// Quick approximation of a mission, computed without the elevation data
+#[derive(Serialize, Deserialize)]
+pub struct MissionEstimate {
+    pub waypoint_count: usize,
+    pub line_count: usize,
+    pub est_flight_time: f64,
+    pub search_area: f64,
+    /// Set when the drone's endurance is known and the mission needs more than one
+    /// battery
+    pub battery_advisory: Option<BatteryAdvisory>,
+}
+
+/// Rough sizing of a mission that won't fit on one battery, so the area can be cut
+/// down before generating
+#[derive(Serialize, Deserialize)]
+pub struct BatteryAdvisory {
+    /// Number of batteries the mission needs
+    pub batteries_required: usize,
+    /// Area in square kilometers one battery can cover at the same settings
+    pub single_battery_area: f64,
+    /// Percentage to shrink the area by to fly it on one battery
+    pub area_reduction_percent: f64,
+}
+
+/// One leg of the mission between consecutive waypoints, for the pilot's briefing
+#[derive(Serialize, Deserialize)]
+pub struct LegSummary {
+    /// Line the leg starts on
+    pub line: usize,
+    /// Compass bearing in degrees
+    pub bearing: f64,
+    /// Ground distance in meters
+    pub distance: f64,
+    /// Flight time in seconds
+    pub time: f64,
+}
+
+/// The search pattern used to cover the area
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(tag = "type")]
+pub enum Pattern {
+    /// Photo survey with line spacing derived from the camera footprint and overlap
+    #[default]
+    Lawnmower,
+    /// SAR creeping-line search with track spacing derived from the sweep width.
+    /// Tracks run perpendicular to `major_axis` (radians, same convention as
+    /// `heading_angle`), which defaults to the long axis of the search area.
+    CreepingLine {
+        sweep: SweepWidth,
+        major_axis: Option<f64>,
+    },
+    /// Lines that follow the terrain contours, one every `interval_m` meters of
+    /// elevation. Needs the elevation data, otherwise a lawnmower is flown instead.
+    Contour { interval_m: f64 },
+    /// A single loop around the edge of the search area with photos at the photo
+    /// spacing, for reconnaissance before a full survey. The aircraft faces into the
+    /// area if `face_inward`, otherwise along the edge, with the gimbal at
+    /// `gimbal_pitch` degrees.
+    Perimeter {
+        face_inward: bool,
+        #[serde(default)]
+        gimbal_pitch: f64,
+    },
+}
+
+/// How the sweep width of a creeping-line search is determined
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SweepWidth {
+    /// Sweep width given directly in meters
+    Fixed { sweep_width_m: f64 },
+    /// Sweep width derived from the lateral detection range of the sensor (m)
+    /// and the target dimensions (m)
+    Detection {
+        detection_range: f64,
+        target_width: f64,
+        target_length: f64,
+    },
+}
+
+impl SweepWidth {
+    /// Returns the sweep width in meters
+    fn meters(&self) -> f64 {
+        match *self {
+            SweepWidth::Fixed { sweep_width_m } => sweep_width_m,
+            // A target is detected as soon as any part of it comes within range,
+            // so its smaller dimension widens the swept corridor
+            SweepWidth::Detection {
+                detection_range,
+                target_width,
+                target_length,
+            } => 2.0 * detection_range + target_width.min(target_length),
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn generate_flightpath(
+    coords: Vec<[f64; 2]>,
+    drone: Drone,
+    config: Option<MissionConfig>,
+    confirmed: Option<bool>,
+) -> Result<FlightPlanResult, String> {
+    let config = config.unwrap_or_default();
+    check_finite_coords(&coords).map_err(|e| e.to_string())?;
+
+    // Check the size with the quick flat-terrain estimate before the slow full pass
+    if let (Some(limit), false) = (config.confirm_above_waypoints, confirmed.unwrap_or(false)) {
+        let estimate = estimate_mission(coords.clone(), drone.clone(), Some(config.clone()))?;
+        let layers = config.altitude_layers.len().max(1);
+        let waypoint_count = estimate.waypoint_count * layers;
+        if waypoint_count > limit {
+            return Ok(FlightPlanResult {
+                search_area: estimate.search_area,
+                est_flight_time: estimate.est_flight_time * layers as f64,
+                warnings: vec![format!(
+                    "The mission has about {} waypoints on {} lines and may take several minutes to generate. Confirm to generate it anyway",
+                    waypoint_count,
+                    estimate.line_count * layers
+                )],
+                confirmation_required: true,
+                ..Default::default()
+            });
+        }
+    }
+
+    let mut result = compute_plan(&coords, &drone, &config).map_err(|e| e.to_string())?;
+
+    if let Some((kmz_path, placemarks)) =
+        write_wqml(&result.waypoints, &result.heading_angle, &drone, &config).await
+    {
+        result.output_files.push(kmz_path);
+        result.kmz_waypoints = Some(placemarks);
+    }
+    result
+        .output_files
+        .extend(write_exports(&result.waypoints, &config).await);
+
+    if let (true, Some(endurance)) = (config.split_by_battery, drone.endurance) {
+        match get_battery_segments(&result.waypoints, endurance, &drone, &config) {
+            Some(segments) if segments.len() > 1 => {
+                if let Some((manifest, files)) = write_battery_kmzs(
+                    &result.waypoints,
+                    &segments,
+                    &result.heading_angle,
+                    &drone,
+                    &config,
+                )
+                .await
+                {
+                    result.output_files.extend(files);
+                    result.battery_manifest = Some(manifest);
+                }
+            }
+            Some(_) => {}
+            None => result
+                .warnings
+                .push("Could not split the mission, it is out of range of a battery".into()),
+        }
+    }
+
+    // Written last so the record has every other field filled in
+    if config.export_formats.contains(&OutputFormat::Json) {
+        match write_flightpath_json(&result, "../output/flightpath.json") {
+            Ok(path) => result.output_files.push(path),
+            Err(e) => println!("Error writing export: {}", e),
+        }
+    }
+    Ok(result)
+}
+
+/// Plans the flight path over the search area without writing any files, so the
+/// planner can be used outside the app
+pub fn compute_plan(
+    coords: &[[f64; 2]],
+    drone: &Drone,
+    config: &MissionConfig,
+) -> Result<FlightPlanResult, FlightPathError> {
+    let input_crs = config.input_crs.as_str();
+    // NaN passes through the projections and poisons every waypoint without an error
+    check_finite_coords(coords)?;
+    // Longitudes wrap at the antimeridian so the averaging and line generation
+    // would plan a path the long way round the world
+    if input_crs == WGS84 && crosses_antimeridian(coords) {
+        return Err(FlightPathError::CrossesAntimeridian);
+    }
+    if config.payload_position > drone.max_payload_position() {
+        return Err(FlightPathError::PayloadPosition {
+            position: config.payload_position,
+            model: drone.model.clone(),
+            max: drone.max_payload_position(),
+        });
+    }
+    if let Some(max) = drone.speed_limit().filter(|max| drone.speed > *max) {
+        return Err(FlightPathError::SpeedLimit {
+            speed: drone.speed,
+            max,
+            model: drone.model.clone(),
+        });
+    }
+    if let Some(interval) = config.action_trigger.param() {
+        check_positive("Action trigger interval", interval)?;
+    }
+    let mut warnings = Vec::new();
+    let (points, duplicates) = remove_duplicate_vertices(coords, input_crs)?;
+    if duplicates > 0 {
+        warnings.push(format!(
+            "Removed {} repeated vertices from the search area",
+            duplicates
+        ));
+    }
+    let search_polygon = match config.area_mode {
+        AreaMode::Polygon => Polygon::new(LineString::from(points), vec![]),
+        AreaMode::ConvexHull => {
+            let hull = MultiPoint::from(points).convex_hull();
+            // Fewer than three points, or points in a line, give a hull with no area
+            if hull.unsigned_area() <= 0.0 {
+                return Err(FlightPathError::DegenerateHull);
+            }
+            hull
+        }
+    };
+    // Lines are laid out over the buffered area, but the area reported is the one drawn
+    let polygon = match config.boundary_buffer_m {
+        Some(distance) => buffer_polygon(&search_polygon, distance, input_crs)?
+            .ok_or(FlightPathError::EmptyArea)?,
+        None => search_polygon.clone(),
+    };
+    let keep_in = config.keep_in.as_ref().map(|coords| {
+        let points: Vec<Coord> = coords.iter().map(|c| Coord::from((c[0], c[1]))).collect();
+        Polygon::new(LineString::from(points), vec![])
+    });
+    let polygon = match &keep_in {
+        Some(keep_in) => {
+            let mut parts = polygon.intersection(keep_in).0;
+            parts.retain(|part| part.unsigned_area() > 0.0);
+            if parts.len() > 1 {
+                warnings.push(format!(
+                    "The keep-in boundary splits the search area into {} parts, only the largest is covered",
+                    parts.len()
+                ));
+            }
+            parts
+                .into_iter()
+                .max_by(|a, b| a.unsigned_area().total_cmp(&b.unsigned_area()))
+                .ok_or(FlightPathError::OutsideKeepIn)?
+        }
+        None => polygon,
+    };
+    let mbr = get_mbr(&polygon);
+    let mbr_coords = mbr.exterior().coords().collect::<Vec<_>>();
+    let vrt_path = String::from(VRT_PATH);
+    // A missing raster falls back to flat terrain, but asking for a band it
+    // doesn't have is a mistake worth reporting
+    let dataset = Dataset::open(&vrt_path).ok();
+    if let Some(dataset) = &dataset {
+        check_band(dataset, config.elevation_band).map_err(FlightPathError::Elevation)?;
+    }
+    let elevation = dataset.as_ref().and_then(|dataset| {
+        ElevationData::new(dataset, config.elevation_band, config.vertical_units)
+            .map_err(|e| println!("Error reading elevation data: {}", e))
+            .ok()
+    });
+
+    let sun_hint =
+        config
+            .capture_time
+            .as_ref()
+            .and_then(|time| match DateTime::parse_from_rfc3339(time) {
+                Ok(time) => {
+                    let centre = get_wgs84_centroid(&polygon, input_crs)?;
+                    Some(get_sun_hint(centre.x, centre.y, time.with_timezone(&Utc)))
+                }
+                Err(_) => {
+                    warnings.push(format!("Could not read capture time '{}'", time));
+                    None
+                }
+            });
+
+    let heading_angle = match config.pattern {
+        Pattern::Lawnmower | Pattern::Contour { .. } | Pattern::Perimeter { .. } => {
+            match (&sun_hint, config.sun_orientation) {
+                (Some(hint), true) => hint.suggested_heading_angle,
+                _ => get_lawnmower_angle(&mbr_coords, input_crs)?,
+            }
+        }
+        Pattern::CreepingLine { major_axis, .. } => {
+            let major_axis = match major_axis {
+                Some(major_axis) => major_axis,
+                None => get_lawnmower_angle(&mbr_coords, input_crs)?,
+            };
+            major_axis + std::f64::consts::PI / 2.0
+        }
+    };
+
+    // Without any layers set the whole area is covered once at the drone altitude
+    let layer_altitudes = if config.altitude_layers.is_empty() {
+        vec![drone.altitude]
+    } else {
+        config.altitude_layers.clone()
+    };
+    let mut waypoints: Vec<Waypoint> = Vec::new();
+    for (layer, &altitude) in layer_altitudes.iter().enumerate() {
+        let layer_drone = Drone {
+            altitude,
+            ..drone.clone()
+        };
+        let spacing = match (config.pattern, config.line_spacing_m) {
+            (_, Some(line_spacing)) => line_spacing,
+            (Pattern::Lawnmower | Pattern::Contour { .. } | Pattern::Perimeter { .. }, None) => {
+                get_line_spacing(&layer_drone, config.quality)
+            }
+            (Pattern::CreepingLine { sweep, .. }, None) => sweep.meters(),
+        };
+        // A zero or NaN spacing would step along the lines forever
+        let spacing = check_positive("Line spacing", spacing)?;
+        let photo_spacing = check_positive(
+            "Photo spacing",
+            get_photo_spacing(&layer_drone, config, spacing),
+        )?;
+
+        if let Some(radius) = config.min_turn_radius_m {
+            // A U-turn between adjacent lines needs a diameter of at most the line spacing
+            if config.turn_mode.is_curved() && spacing < 2.0 * radius {
+                warnings.push(format!(
+                    "Line spacing of {:.1} m is tighter than the {:.1} m turn radius allows, turns between lines may overshoot",
+                    spacing, radius
+                ));
+            }
+        }
+
+        let pattern_waypoints = match config.pattern {
+            Pattern::Contour { interval_m } => {
+                let waypoints = get_contour_waypoints(
+                    &polygon,
+                    check_positive("Contour interval", interval_m)?,
+                    photo_spacing,
+                    &vrt_path,
+                    &layer_drone,
+                    config,
+                    &mut warnings,
+                )?;
+                if waypoints.is_none() {
+                    warnings.push("No elevation data for contour lines, using a lawnmower".into());
+                }
+                waypoints
+            }
+            Pattern::Perimeter {
+                face_inward,
+                gimbal_pitch,
+            } => Some(get_perimeter_waypoints(
+                &polygon,
+                photo_spacing,
+                face_inward,
+                gimbal_pitch,
+                &vrt_path,
+                &layer_drone,
+                config,
+            )?),
+            _ => None,
+        };
+        let straight_lines = pattern_waypoints.is_none();
+        let mut layer_waypoints = match pattern_waypoints {
+            Some(waypoints) => waypoints,
+            None => get_waypoints_with_slope_adjustment(
+                &polygon,
+                &heading_angle,
+                &spacing,
+                elevation.as_ref().map(|e| e as &dyn ElevationSource),
+                &layer_drone,
+                config,
+                &mut warnings,
+            )?,
+        };
+
+        if config.close_line_ends && straight_lines {
+            layer_waypoints =
+                close_line_ends(&layer_waypoints, &polygon, spacing, &layer_drone, input_crs)?;
+        }
+
+        // Number the lines on from the previous layer so they stay unique
+        let line_offset = waypoints.last().map_or(0, |w| w.line + 1);
+        let fallback_altitude = config.fallback_altitude.unwrap_or(altitude);
+        let geoid_offset = config.geoid_offset_m.unwrap_or(0.0);
+        let mut flat_heights = 0;
+        let mut ellipsoid_heights = 0;
+        for waypoint in &mut layer_waypoints {
+            waypoint.line += line_offset;
+            waypoint.layer = layer;
+            waypoint.altitude = match (waypoint.terrain_elevation, config.height_mode) {
+                // Absolute heights have to follow the terrain to keep the clearance. The
+                // terrain is above mean sea level, so lift it onto the ellipsoid.
+                (Some(terrain), HeightMode::Wgs84) => {
+                    ellipsoid_heights += 1;
+                    terrain + geoid_offset + altitude
+                }
+                (Some(_), HeightMode::RelativeToStartPoint) => altitude,
+                (None, height_mode) => {
+                    if height_mode == HeightMode::Wgs84 {
+                        flat_heights += 1;
+                    }
+                    fallback_altitude
+                }
+            };
+        }
+        if flat_heights > 0 {
+            warnings.push(format!(
+                "No elevation data for {} waypoints, their absolute heights are {:.0} m above the ellipsoid rather than the terrain",
+                flat_heights, fallback_altitude
+            ));
+        }
+        if ellipsoid_heights > 0 && config.geoid_offset_m.is_none() {
+            warnings.push(
+                "No geoid offset set, so WGS84 heights treat the elevation data as ellipsoidal heights. Over New Zealand the terrain is up to about 40 m higher above the ellipsoid than the data shows, so set the geoid offset for the area".into(),
+            );
+        }
+        waypoints.extend(layer_waypoints);
+    }
+    // Each layer repeats the same warnings
+    warnings.dedup();
+    if config.reverse {
+        reverse_mission(&mut waypoints);
+    }
+    if let Some(zoom) = config.zoom {
+        apply_pass_zoom(&mut waypoints, zoom);
+    }
+    if let Some(buffer) = config.turnaround_buffer_m {
+        waypoints = add_turnaround_buffer(&waypoints, buffer)?;
+    }
+    let clearance_adjustments = enforce_min_clearance(&mut waypoints, config, &mut warnings);
+    let ceiling_adjustments = enforce_max_altitude(&mut waypoints, config, &mut warnings);
+    scale_footprints_to_agl(&mut waypoints, &layer_altitudes, config);
+    // Where each waypoint was when the adjustments were recorded, kept in step with
+    // the waypoints as they're removed so the indices can be brought up to date
+    let mut origins: Vec<usize> = (0..waypoints.len()).collect();
+    let mut merged_waypoints = 0;
+    if let Some(tolerance) = config.merge_tolerance {
+        // Clamped waypoints bend the flown profile, so they're never merged away
+        let pinned: HashSet<usize> = clearance_adjustments
+            .iter()
+            .chain(&ceiling_adjustments)
+            .copied()
+            .collect();
+        let keep = merge_collinear_waypoints(&waypoints, tolerance, &pinned)?;
+        merged_waypoints = retain_waypoints(&mut waypoints, &mut origins, &keep);
+    }
+    if let Some(ramp) = &config.speed_ramp {
+        apply_speed_ramp(&mut waypoints, ramp, drone.speed);
+    }
+    let out_of_range = get_out_of_range_waypoints(&waypoints, config, &mut warnings);
+    if config.clip_to_range && !out_of_range.is_empty() {
+        let out_of_range: HashSet<usize> = out_of_range.iter().copied().collect();
+        let keep: Vec<bool> = (0..waypoints.len())
+            .map(|i| !out_of_range.contains(&i))
+            .collect();
+        retain_waypoints(&mut waypoints, &mut origins, &keep);
+    }
+    let out_of_range: Vec<usize> = out_of_range.into_iter().map(|i| origins[i]).collect();
+    if let Some(keep_in) = &keep_in {
+        // Turnaround buffers and added line ends can reach past the clipped area
+        let keep = inside_keep_in(&waypoints, keep_in, input_crs)?;
+        let removed = retain_waypoints(&mut waypoints, &mut origins, &keep);
+        if removed > 0 {
+            warnings.push(format!(
+                "{} waypoints outside the keep-in boundary were removed",
+                removed
+            ));
+        }
+    }
+    let search_area = calculate_search_area(&search_polygon, input_crs)?;
+    let mut truncated_area = None;
+    if let Some(max_distance) = config.max_total_distance_m {
+        if let Some((removed, share)) = truncate_to_distance(&mut waypoints, max_distance) {
+            // Assumes the area is covered evenly along the flight path
+            let area = search_area * share;
+            warnings.push(format!(
+                "The mission was cut off at {:.0} m, leaving {} waypoints and about {:.3} square kilometers unflown",
+                max_distance, removed, area
+            ));
+            truncated_area = Some(area);
+        }
+        origins.truncate(waypoints.len());
+    }
+    // Drop the indices of waypoints that were removed and renumber the rest
+    let remap = |indices: Vec<usize>| -> Vec<usize> {
+        indices
+            .iter()
+            .filter_map(|i| origins.binary_search(i).ok())
+            .collect()
+    };
+    let clearance_adjustments = remap(clearance_adjustments);
+    let ceiling_adjustments = remap(ceiling_adjustments);
+    let out_of_range = remap(out_of_range);
+    if !ceiling_adjustments.is_empty() {
+        warnings.push(format!(
+            "{} waypoints were lowered to the maximum altitude, overlap will be lower there",
+            ceiling_adjustments.len()
+        ));
+    }
+    if waypoints.is_empty() {
+        return Err(FlightPathError::NoWaypoints);
+    }
+    check_drone_limits(&waypoints, drone, config)?;
+    let hidden_waypoints = match config.visibility_from {
+        Some(station) => get_hidden_waypoints(&waypoints, station, &vrt_path, config)?,
+        None => Vec::new(),
+    };
+    if !hidden_waypoints.is_empty() {
+        warnings.push(format!(
+            "{} waypoints are hidden by terrain from the ground station",
+            hidden_waypoints.len()
+        ));
+    }
+
+    let est_flight_time = calculate_flight_time(&waypoints, drone.speed, config);
+    let (min_gsd, max_gsd) = get_gsd_range(&waypoints);
+    let batteries_required = drone.endurance.and_then(|endurance| {
+        calculate_batteries_required(&waypoints, est_flight_time, endurance, drone, config)
+    });
+
+    Ok(FlightPlanResult {
+        waypoints,
+        heading_angle,
+        search_area,
+        est_flight_time,
+        min_gsd,
+        max_gsd,
+        merged_waypoints,
+        clearance_adjustments,
+        ceiling_adjustments,
+        warnings,
+        batteries_required,
+        out_of_range,
+        output_files: Vec::new(),
+        sun_hint,
+        battery_manifest: None,
+        confirmation_required: false,
+        kmz_waypoints: None,
+        hidden_waypoints,
+        truncated_area,
+    })
+}
+
+/// Removes vertices within DUPLICATE_VERTEX_TOLERANCE of the vertex before them, the
+/// zero-length edges sloppy digitizing leaves behind, which upset the minimum
+/// rectangle and containment tests. Returns the points left and how many were removed.
+fn remove_duplicate_vertices(
+    coords: &[[f64; 2]],
+    input_crs: &str,
+) -> Result<(Vec<Coord>, usize), FlightPathError> {
+    let points: Vec<Coord> = coords.iter().map(|c| Coord::from((c[0], c[1]))).collect();
+    let points_meters = get_coord_meters(&points.iter().collect::<Vec<_>>(), input_crs)?;
+
+    let mut kept = Vec::with_capacity(points.len());
+    let mut last_kept: Option<Coord> = None;
+    for (point, meters) in points.iter().zip(points_meters) {
+        if let Some(last) = last_kept {
+            let delta = meters - last;
+            if delta.x.hypot(delta.y) < DUPLICATE_VERTEX_TOLERANCE {
+                continue;
+            }
+        }
+        kept.push(*point);
+        last_kept = Some(meters);
+    }
+    let removed = points.len() - kept.len();
+    Ok((kept, removed))
+}
+
+/// Returns the value if it's a finite number above zero, for the spacings and
+/// intervals the generators step by
+pub(crate) fn check_positive(setting: &'static str, value: f64) -> Result<f64, FlightPathError> {
+    if value.is_finite() && value > 0.0 {
+        Ok(value)
+    } else {
+        Err(FlightPathError::InvalidSetting { setting, value })
+    }
+}
+
+/// Checks every coordinate is a finite number
+fn check_finite_coords(coords: &[[f64; 2]]) -> Result<(), FlightPathError> {
+    match coords
+        .iter()
+        .position(|c| !c[0].is_finite() || !c[1].is_finite())
+    {
+        Some(index) => Err(FlightPathError::NonFiniteCoordinate { index }),
+        None => Ok(()),
+    }
+}
+
+/// Returns a fast estimate of the mission size using the flat-terrain geometry only,
+/// so it can be run on every change in the UI before the full generation
+#[tauri::command]
+pub fn estimate_mission(
+    coords: Vec<[f64; 2]>,
+    drone: Drone,
+    config: Option<MissionConfig>,
+) -> Result<MissionEstimate, String> {
+    let config = config.unwrap_or_default();
+    estimate_plan(&coords, &drone, &config).map_err(|e| e.to_string())
 }
 
+/// Estimates the mission size from the flat-terrain lawnmower over the area
+fn estimate_plan(
+    coords: &[[f64; 2]],
+    drone: &Drone,
+    config: &MissionConfig,
+) -> Result<MissionEstimate, FlightPathError> {
+    let input_crs = config.input_crs.as_str();
+    let points: Vec<Coord> = coords.iter().map(|c| Coord::from((c[0], c[1]))).collect();
+    let polygon = Polygon::new(LineString::from(points), vec![]);
+    let mbr = get_mbr(&polygon);
+    let mbr_coords = mbr.exterior().coords().collect::<Vec<_>>();
+
+    let heading_angle = get_lawnmower_angle(&mbr_coords, input_crs)?;
+    let spacing = check_positive(
+        "Line spacing",
+        config
+            .line_spacing_m
+            .unwrap_or_else(|| get_line_spacing(drone, config.quality)),
+    )?;
+    let photo_spacing = check_positive("Photo spacing", get_photo_spacing(drone, config, spacing))?;
+    let waypoints = get_waypoints_fallback(
+        &polygon,
+        &heading_angle,
+        &spacing,
+        &photo_spacing,
+        get_anchor_meters(config)?,
+        drone,
+        input_crs,
+    )?;
+
+    let est_flight_time = calculate_flight_time(&waypoints, drone.speed, config);
+    let search_area = calculate_search_area(&polygon, input_crs)?;
+    // Flight time grows roughly in proportion to the area at a fixed spacing and
+    // speed, so one battery covers the same share of the area as of the time
+    let battery_advisory = drone
+        .endurance
+        .and_then(|endurance| get_survey_time_per_battery(&waypoints, endurance, drone, config))
+        .filter(|survey_time| est_flight_time > *survey_time)
+        .map(|survey_time| {
+            let share = survey_time / est_flight_time;
+            BatteryAdvisory {
+                batteries_required: (est_flight_time / survey_time).ceil() as usize,
+                single_battery_area: search_area * share,
+                area_reduction_percent: (1.0 - share) * 100.0,
+            }
+        });
+
+    Ok(MissionEstimate {
+        waypoint_count: waypoints.len(),
+        line_count: waypoints.iter().map(|w| w.line + 1).max().unwrap_or(0),
+        est_flight_time,
+        search_area,
+        battery_advisory,
+    })
+}
+
+/// Returns the photo footprint for the drone at a single WGS84 point, so the UI can
+/// preview how the camera settings change the coverage. `bearing_deg` is the compass
+/// direction of travel. The slope is taken from the elevation data if it is available.
 #[tauri::command]
-pub async fn generate_flightpath(coords: Vec<[f64; 2]>, drone: Drone) -> FlightPlanResult {
+pub fn footprint_at(
+    point: [f64; 2],
+    drone: Drone,
+    bearing_deg: f64,
+) -> Result<CoverageRect, String> {
+    let point_meters = get_coord_meters(&[&Coord::from((point[0], point[1]))], WGS84)
+        .map_err(|e| e.to_string())?[0];
+
+    let slope_angle = Dataset::open(VRT_PATH)
+        .ok()
+        .and_then(|dataset| {
+            ElevationData::new(&dataset, DEFAULT_BAND, None)
+                .ok()
+                .map(|elevation| calculate_slope_at_point(point_meters, &elevation))
+        })
+        .unwrap_or(0.0);
+
+    // Footprints are rotated perpendicular to the planar angle of travel
+    let travel_angle = compass_bearing_to_math_angle(bearing_deg);
+    let perp_angle = travel_angle + std::f64::consts::PI / 2.0;
+
+    let to_wgs84 = get_projection(NZTM, WGS84).map_err(|e| e.to_string())?;
+    generate_coverage_rect(&point_meters, &slope_angle, &perp_angle, &drone, &to_wgs84)
+        .map_err(|e| e.to_string())
+}
+
+/// Returns just the photo footprints of a flat-terrain lawnmower over the area, for
+/// a quick coverage overlay without building waypoints or writing any files
+#[tauri::command]
+pub fn coverage_footprints(
+    coords: Vec<[f64; 2]>,
+    drone: Drone,
+) -> Result<Vec<CoverageRect>, String> {
     let points: Vec<Coord> = coords.iter().map(|c| Coord::from((c[0], c[1]))).collect();
-    let polygon = Polygon::new(LineString::from(points.clone()), vec![]);
-    let mbr = MinimumRotatedRect::minimum_rotated_rect(&polygon).unwrap();
+    let polygon = Polygon::new(LineString::from(points), vec![]);
+    let mbr = get_mbr(&polygon);
     let mbr_coords = mbr.exterior().coords().collect::<Vec<_>>();
-    let vrt_path = String::from("../data/elevation.vrt");
 
-    let coverage = get_ground_coverage(&drone);
-    let heading_angle = get_lawnmower_angle(&mbr_coords);
-    let spacing = coverage * (100.0 - drone.overlap) / 100.0;
+    get_lawnmower_footprints(&polygon, &mbr_coords, &drone).map_err(|e| e.to_string())
+}
+
+/// Returns the flat-terrain lawnmower footprints over the WGS84 polygon
+fn get_lawnmower_footprints(
+    polygon: &Polygon,
+    mbr_coords: &[&Coord],
+    drone: &Drone,
+) -> Result<Vec<CoverageRect>, FlightPathError> {
+    let heading_angle = get_lawnmower_angle(mbr_coords, WGS84)?;
+    let perp_angle = heading_angle + std::f64::consts::PI / 2.0;
+    let spacing = get_line_spacing(drone, None);
+    let to_wgs84 = get_projection(NZTM, WGS84)?;
+    get_fallback_points(polygon, &heading_angle, &spacing, &spacing, None, WGS84)?
+        .iter()
+        .map(|(point, _)| generate_coverage_rect(point, &0.0, &perp_angle, drone, &to_wgs84))
+        .collect()
+}
+
+/// Returns the WGS84 corners of the minimum rotated rectangle around the area and
+/// the compass bearing in degrees the lines would be flown along, so the UI can
+/// show the orientation while the polygon is edited
+#[tauri::command]
+pub fn minimum_rect(coords: Vec<[f64; 2]>) -> Result<(Vec<[f64; 2]>, f64), String> {
+    let points: Vec<Coord> = coords.iter().map(|c| Coord::from((c[0], c[1]))).collect();
+    let polygon = Polygon::new(LineString::from(points), vec![]);
+    let mbr = get_mbr(&polygon);
+    let mbr_coords = mbr.exterior().coords().collect::<Vec<_>>();
+
+    let heading_angle = get_lawnmower_angle(&mbr_coords, WGS84).map_err(|e| e.to_string())?;
+    let bearing = math_angle_to_compass_bearing(heading_angle);
+    Ok((mbr_coords.iter().map(|c| [c.x, c.y]).collect(), bearing))
+}
+
+/// Returns the bearing, distance and flight time of each leg of the plan, in order,
+/// so a turn-by-turn flight plan can be written for the pilot
+#[tauri::command]
+pub fn mission_summary(result: FlightPlanResult, drone: Drone) -> Vec<LegSummary> {
+    let to_nztm = get_nztm_proj();
+    result
+        .waypoints
+        .windows(2)
+        .map(|pair| {
+            let (distance, track) = get_leg(pair[0].position, pair[1].position, to_nztm.as_ref());
+            LegSummary {
+                line: pair[0].line,
+                bearing: track.to_degrees().rem_euclid(360.0),
+                distance,
+                time: distance / pair[0].speed.unwrap_or(drone.speed),
+            }
+        })
+        .collect()
+}
+
+/// Returns the flight time in minutes of an existing plan flown at `speed_ms`, to see
+/// how the speed changes the time without regenerating the waypoints. Waypoints
+/// with their own speed, such as on a speed ramp, keep it.
+#[tauri::command]
+pub fn retime_plan(
+    result: FlightPlanResult,
+    speed_ms: f64,
+    config: Option<MissionConfig>,
+) -> Result<f64, String> {
+    if !(speed_ms > 0.0 && speed_ms.is_finite()) {
+        return Err(format!("Speed must be a positive number, got {}", speed_ms));
+    }
+    let config = config.unwrap_or_default();
+    Ok(calculate_flight_time(&result.waypoints, speed_ms, &config))
+}
+
+/// Joins two already computed plans into one mission, such as a hand-edited overview
+/// and a generated detail survey. The plans are flown one after the other, in the
+/// order and direction that gives the shortest transit between them. `areas` are the
+/// search areas of `a` and `b` in the input CRS, for the area of their union.
+#[tauri::command]
+pub fn merge_plans(
+    a: FlightPlanResult,
+    b: FlightPlanResult,
+    areas: [Vec<[f64; 2]>; 2],
+    drone: Drone,
+    config: Option<MissionConfig>,
+) -> Result<FlightPlanResult, String> {
+    let config = config.unwrap_or_default();
+    let input_crs = config.input_crs.as_str();
+    let to_nztm = get_nztm_proj();
+
+    // Try each plan first, with the second flown forwards or reversed
+    let transit = |first: &FlightPlanResult, second: &FlightPlanResult, reversed: bool| {
+        let start = if reversed {
+            second.waypoints.last()
+        } else {
+            second.waypoints.first()
+        };
+        match (first.waypoints.last(), start) {
+            (Some(end), Some(start)) => {
+                get_distance_meters(end.position, start.position, to_nztm.as_ref())
+            }
+            _ => 0.0,
+        }
+    };
+    let (a_first, reversed) = [(true, false), (true, true), (false, false), (false, true)]
+        .into_iter()
+        .min_by(|x, y| {
+            let distance = |&(a_first, reversed): &(bool, bool)| {
+                if a_first {
+                    transit(&a, &b, reversed)
+                } else {
+                    transit(&b, &a, reversed)
+                }
+            };
+            distance(x).total_cmp(&distance(y))
+        })
+        .unwrap_or((true, false));
+    let (mut first, mut second) = if a_first { (a, b) } else { (b, a) };
+    if reversed {
+        reverse_plan(&mut second);
+    }
+
+    // The second plan's lines and indices carry on from the end of the first
+    let offset = first.waypoints.len();
+    let line_offset = first
+        .waypoints
+        .iter()
+        .map(|w| w.line + 1)
+        .max()
+        .unwrap_or(0);
+    for waypoint in &mut second.waypoints {
+        waypoint.line += line_offset;
+    }
+    let shift = |indices: Vec<usize>| indices.into_iter().map(move |i| i + offset);
+    first
+        .clearance_adjustments
+        .extend(shift(second.clearance_adjustments));
+    first
+        .ceiling_adjustments
+        .extend(shift(second.ceiling_adjustments));
+    first.out_of_range.extend(shift(second.out_of_range));
+    first
+        .hidden_waypoints
+        .extend(shift(second.hidden_waypoints));
+    first.waypoints.extend(second.waypoints);
+    first.warnings.extend(second.warnings);
+    first.warnings.dedup();
+
+    let [area_a, area_b] = areas.map(|coords| {
+        let points: Vec<Coord> = coords.iter().map(|c| Coord::from((c[0], c[1]))).collect();
+        Polygon::new(LineString::from(points), vec![])
+    });
+    let search_area = area_a
+        .union(&area_b)
+        .into_iter()
+        .map(|part| calculate_search_area(&part, input_crs))
+        .sum::<Result<f64, _>>()
+        .map_err(|e| e.to_string())?;
+    let est_flight_time = calculate_flight_time(&first.waypoints, drone.speed, &config);
+    let (min_gsd, max_gsd) = get_gsd_range(&first.waypoints);
+    let batteries_required = drone.endurance.and_then(|endurance| {
+        calculate_batteries_required(
+            &first.waypoints,
+            est_flight_time,
+            endurance,
+            &drone,
+            &config,
+        )
+    });
+
+    Ok(FlightPlanResult {
+        search_area,
+        est_flight_time,
+        min_gsd,
+        max_gsd,
+        merged_waypoints: first.merged_waypoints + second.merged_waypoints,
+        batteries_required,
+        output_files: Vec::new(),
+        battery_manifest: None,
+        confirmation_required: false,
+        kmz_waypoints: None,
+        ..first
+    })
+}
+
+/// Reverses the flying order of a computed plan, keeping its lists of waypoint
+/// indices pointing at the same waypoints
+fn reverse_plan(plan: &mut FlightPlanResult) {
+    reverse_mission(&mut plan.waypoints);
+    let last = plan.waypoints.len().saturating_sub(1);
+    for indices in [
+        &mut plan.clearance_adjustments,
+        &mut plan.ceiling_adjustments,
+        &mut plan.out_of_range,
+        &mut plan.hidden_waypoints,
+    ] {
+        for index in indices.iter_mut() {
+            *index = last.saturating_sub(*index);
+        }
+        indices.sort_unstable();
+    }
+}
+
+/// Collapses runs of collinear waypoints at the same altitude down to their endpoints.
+/// A waypoint is only removed if it lies within `tolerance` meters of the straight
+/// line between its neighbours and carries no action of its own (such as a zoom).
+/// Photos are no longer taken at the removed positions, so this is intended for
+/// missions that capture on a distance or time interval. Waypoints in `pinned` are
+/// always kept. Returns which waypoints to keep.
+fn merge_collinear_waypoints(
+    waypoints: &[Waypoint],
+    tolerance: f64,
+    pinned: &HashSet<usize>,
+) -> Result<Vec<bool>, FlightPathError> {
+    let mut keep = vec![true; waypoints.len()];
+    if waypoints.len() < 3 {
+        return Ok(keep);
+    }
+
+    let positions: Vec<Coord> = waypoints
+        .iter()
+        .map(|w| Coord::from((w.position[0], w.position[1])))
+        .collect();
+    let positions_meters = get_coord_meters(&positions.iter().collect::<Vec<_>>(), WGS84)?;
+
+    let mut last_kept = 0;
+    for i in 1..waypoints.len() - 1 {
+        let (prev, current, next) = (&waypoints[last_kept], &waypoints[i], &waypoints[i + 1]);
+        let removable = !pinned.contains(&i)
+            && current.zoom.is_none()
+            && current.timelapse.is_none()
+            && current.capture == prev.capture
+            && current.capture == next.capture
+            && current.line == prev.line
+            && current.line == next.line
+            && (current.altitude - prev.altitude).abs() < f64::EPSILON
+            && (current.altitude - next.altitude).abs() < f64::EPSILON
+            && is_between_collinear(
+                positions_meters[last_kept],
+                positions_meters[i],
+                positions_meters[i + 1],
+                tolerance,
+            );
+
+        if removable {
+            keep[i] = false;
+        } else {
+            last_kept = i;
+        }
+    }
+    Ok(keep)
+}
+
+/// Keeps the waypoints marked in `keep`, along with their entries in `origins`, and
+/// returns how many were removed
+fn retain_waypoints(
+    waypoints: &mut Vec<Waypoint>,
+    origins: &mut Vec<usize>,
+    keep: &[bool],
+) -> usize {
+    let original_len = waypoints.len();
+    let mut kept = keep.iter();
+    waypoints.retain(|_| *kept.next().unwrap_or(&true));
+    let mut kept = keep.iter();
+    origins.retain(|_| *kept.next().unwrap_or(&true));
+    original_len - waypoints.len()
+}
+
+/// Returns true if `point` lies on the segment from `start` to `end`, within `tolerance` meters
+fn is_between_collinear(start: Coord, point: Coord, end: Coord, tolerance: f64) -> bool {
+    let segment = end - start;
+    let to_point = point - start;
+    let length = (segment.x.powi(2) + segment.y.powi(2)).sqrt();
+    if length < f64::EPSILON {
+        return false;
+    }
+
+    let cross = segment.x * to_point.y - segment.y * to_point.x;
+    let along = (segment.x * to_point.x + segment.y * to_point.y) / length;
+    (cross / length).abs() <= tolerance && (0.0..=length).contains(&along)
+}
+
+/// Raises any waypoint that would be closer to the terrain than the configured minimum
+/// clearance and returns the indices of the raised waypoints. Waypoints without
+/// terrain data are left as they are and counted in the warnings.
+fn enforce_min_clearance(
+    waypoints: &mut [Waypoint],
+    config: &MissionConfig,
+    warnings: &mut Vec<String>,
+) -> Vec<usize> {
+    let Some(reference) = get_height_reference(config) else {
+        warnings.push("No home point set, the minimum terrain clearance was not enforced".into());
+        return Vec::new();
+    };
+
+    let mut adjusted = Vec::new();
+    let mut unchecked = 0;
+    for (i, waypoint) in waypoints.iter_mut().enumerate() {
+        let Some(terrain) = waypoint.terrain_elevation else {
+            unchecked += 1;
+            continue;
+        };
+        let clearance = waypoint.altitude + reference - terrain;
+        if clearance < config.min_clearance_m {
+            waypoint.altitude += config.min_clearance_m - clearance;
+            adjusted.push(i);
+        }
+    }
+    if unchecked > 0 {
+        warnings.push(format!(
+            "{} waypoints have no terrain data, their minimum clearance was not checked",
+            unchecked
+        ));
+    }
+    adjusted
+}
+
+/// Returns the indices of the waypoints that can't be seen from a ground station at
+/// the WGS84 `station`, because the terrain rises above the sight line between them.
+/// The terrain is sampled about once a raster pixel along each line. Returns nothing
+/// if there is no elevation data or the waypoint heights can't be referenced.
+fn get_hidden_waypoints(
+    waypoints: &[Waypoint],
+    station: [f64; 2],
+    vrt_path: &str,
+    config: &MissionConfig,
+) -> Result<Vec<usize>, FlightPathError> {
+    let Ok(dataset) = Dataset::open(vrt_path) else {
+        println!("Warning: no elevation data, cannot check visibility");
+        return Ok(Vec::new());
+    };
+    let elevation = match ElevationData::new(&dataset, config.elevation_band, config.vertical_units)
+    {
+        Ok(elevation) => elevation,
+        Err(e) => {
+            println!("Error reading elevation data: {}", e);
+            return Ok(Vec::new());
+        }
+    };
+    let Some(reference) = get_height_reference(config) else {
+        println!("Warning: no home point set, cannot check visibility");
+        return Ok(Vec::new());
+    };
+
+    let station = get_coord_meters(&[&Coord::from((station[0], station[1]))], WGS84)?[0];
+    let Some(station_height) = elevation
+        .elevation_at(station.x, station.y)
+        .map(|terrain| terrain + STATION_HEIGHT)
+    else {
+        println!("Warning: the ground station is outside the elevation data");
+        return Ok(Vec::new());
+    };
+
+    let positions: Vec<Coord> = waypoints
+        .iter()
+        .map(|w| Coord::from((w.position[0], w.position[1])))
+        .collect();
+    let positions_meters = get_coord_meters(&positions.iter().collect::<Vec<_>>(), WGS84)?;
+
+    let mut hidden = Vec::new();
+    for (i, (waypoint, target)) in waypoints.iter().zip(positions_meters).enumerate() {
+        let target_height = waypoint.altitude + reference;
+        let delta = target - station;
+        let length = (delta.x.powi(2) + delta.y.powi(2)).sqrt();
+        let samples = ((length / elevation.pixel_size()) as usize).min(MAX_SIGHT_SAMPLES);
+
+        let blocked = (1..samples).any(|k| {
+            let t = k as f64 / samples as f64;
+            let point = station + delta * t;
+            let sight_height = station_height + (target_height - station_height) * t;
+            elevation
+                .elevation_at(point.x, point.y)
+                .is_some_and(|terrain| terrain > sight_height)
+        });
+        if blocked {
+            hidden.push(i);
+        }
+    }
+    Ok(hidden)
+}
+
+/// Returns the indices of the waypoints further than the maximum range from the home point
+fn get_out_of_range_waypoints(
+    waypoints: &[Waypoint],
+    config: &MissionConfig,
+    warnings: &mut Vec<String>,
+) -> Vec<usize> {
+    let Some(max_range) = config.max_range_m else {
+        return Vec::new();
+    };
+    let Some(home) = config.home_point else {
+        warnings
+            .push("A maximum range is set but there is no home point to measure it from".into());
+        return Vec::new();
+    };
+
+    // Project the home point once and measure each waypoint in the NZTM plane,
+    // falling back to great-circle distances if PROJ isn't available
+    let home = [home[0], home[1]];
+    let to_nztm = get_nztm_proj();
+    let home_meters = to_nztm
+        .as_ref()
+        .and_then(|to_nztm| to_nztm.convert((home[0], home[1])).ok());
+    let distance_from_home = |position: [f64; 2]| {
+        let planar = to_nztm
+            .as_ref()
+            .zip(home_meters)
+            .and_then(|(to_nztm, (hx, hy))| {
+                let (x, y) = to_nztm.convert((position[0], position[1])).ok()?;
+                Some((x - hx).hypot(y - hy))
+            });
+        planar.unwrap_or_else(|| haversine_distance(home, position))
+    };
+    let out_of_range: Vec<usize> = waypoints
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| distance_from_home(w.position) > max_range)
+        .map(|(i, _)| i)
+        .collect();
+
+    if !out_of_range.is_empty() {
+        warnings.push(format!(
+            "{} waypoints are more than {:.0} m from the home point",
+            out_of_range.len(),
+            max_range
+        ));
+    }
+    out_of_range
+}
+
+/// Extends every flight line past its ends by `buffer` meters with non-capturing
+/// waypoints, so the turn between lines happens outside the search area and the
+/// drone is at speed and straight by the time it takes the first photo
+fn add_turnaround_buffer(
+    waypoints: &[Waypoint],
+    buffer: f64,
+) -> Result<Vec<Waypoint>, FlightPathError> {
+    let to_nztm = get_projection(WGS84, NZTM)?;
+    let to_wgs84 = get_projection(NZTM, WGS84)?;
+    let mut buffered = Vec::new();
+
+    for line in waypoints.chunk_by(|a, b| a.line == b.line) {
+        let (first, last) = (line[0], line[line.len() - 1]);
+        let ends = project_coords(
+            &[
+                &Coord::from((first.position[0], first.position[1])),
+                &Coord::from((last.position[0], last.position[1])),
+            ],
+            &to_nztm,
+        )?;
+        let direction = ends[1] - ends[0];
+        let length = (direction.x.powi(2) + direction.y.powi(2)).sqrt();
+        if length < f64::EPSILON {
+            buffered.extend_from_slice(line);
+            continue;
+        }
+        let offset = direction * (buffer / length);
+
+        let lead_in = ends[0] - offset;
+        let lead_out = ends[1] + offset;
+        if let Ok(position) = to_wgs84.convert((lead_in.x, lead_in.y)) {
+            buffered.push(transit_waypoint(&first, [position.0, position.1]));
+        }
+        buffered.extend_from_slice(line);
+        if let Ok(position) = to_wgs84.convert((lead_out.x, lead_out.y)) {
+            buffered.push(transit_waypoint(&last, [position.0, position.1]));
+        }
+    }
+    Ok(buffered)
+}
+
+/// Adds capturing waypoints past each end of every line until a photo footprint
+/// reaches the search area boundary. The boundary is measured along the centre of
+/// the line and either side of it across the line's share of the swath, so an
+/// oblique edge is covered as well as a square one.
+fn close_line_ends(
+    waypoints: &[Waypoint],
+    polygon: &Polygon,
+    spacing: f64,
+    drone: &Drone,
+    input_crs: &str,
+) -> Result<Vec<Waypoint>, FlightPathError> {
+    let to_nztm = get_projection(WGS84, NZTM)?;
+    let to_wgs84 = get_projection(NZTM, WGS84)?;
+    let search_coords_meters =
+        get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>(), input_crs)?;
+    let search_polygon_meters = Polygon::new(LineString::from(search_coords_meters), vec![]);
+    let half_coverage = get_ground_coverage(drone) / 2.0;
+
+    // Distance from the point to the boundary in the given direction, or 0 if the
+    // point is already outside the area
+    let distance_to_boundary = |point: Coord, direction: Coord| -> f64 {
+        if search_polygon_meters.coordinate_position(&point) == CoordPos::Outside {
+            return 0.0;
+        }
+        let ray = LineString::from(vec![point, point + direction * (4.0 * half_coverage)]);
+        let along = |p: Coord| (p - point).x * direction.x + (p - point).y * direction.y;
+        search_polygon_meters
+            .clip(&MultiLineString::new(vec![ray]), false)
+            .into_iter()
+            .filter_map(|segment| {
+                // The part of the ray starting at the point ends at the boundary
+                let first = along(*segment.0.first()?);
+                let last = along(*segment.0.last()?);
+                (first.min(last) < 1e-6).then_some(first.max(last))
+            })
+            .fold(0.0, f64::max)
+    };
+
+    let mut closed = Vec::new();
+    for line in waypoints.chunk_by(|a, b| a.line == b.line) {
+        let (first, last) = (line[0], line[line.len() - 1]);
+        let ends = project_coords(
+            &[
+                &Coord::from((first.position[0], first.position[1])),
+                &Coord::from((last.position[0], last.position[1])),
+            ],
+            &to_nztm,
+        )?;
+        let direction = ends[1] - ends[0];
+        let length = (direction.x.powi(2) + direction.y.powi(2)).sqrt();
+        if length < f64::EPSILON {
+            closed.extend_from_slice(line);
+            continue;
+        }
+        let direction = direction / length;
+        let across = Coord {
+            x: -direction.y,
+            y: direction.x,
+        } * (spacing / 2.0);
+        let perp_angle = direction.y.atan2(direction.x) + std::f64::consts::PI / 2.0;
+
+        // Photos past the end at `end`, heading away from the line in `outwards`
+        let extend = |template: &Waypoint,
+                      end: Coord,
+                      outwards: Coord|
+         -> Result<Vec<Waypoint>, FlightPathError> {
+            let reach = [end - across, end, end + across]
+                .into_iter()
+                .map(|point| distance_to_boundary(point, outwards))
+                .fold(0.0, f64::max);
+            let mut extra = Vec::new();
+            let mut distance = 0.0;
+            while distance + half_coverage < reach - 1e-6 {
+                distance = (distance + spacing).min(reach - half_coverage);
+                let point = end + outwards * distance;
+                if let Ok((lon, lat)) = to_wgs84.convert((point.x, point.y)) {
+                    extra.push(Waypoint {
+                        coverage_rect: generate_coverage_rect(
+                            &point,
+                            &0.0,
+                            &perp_angle,
+                            drone,
+                            &to_wgs84,
+                        )?,
+                        position: [lon, lat],
+                        ..*template
+                    });
+                }
+            }
+            Ok(extra)
+        };
+
+        closed.extend(extend(&first, ends[0], -direction)?.into_iter().rev());
+        closed.extend_from_slice(line);
+        closed.extend(extend(&last, ends[1], direction)?);
+    }
+    Ok(closed)
+}
+
+/// Returns a non-capturing copy of the waypoint at a new position
+fn transit_waypoint(template: &Waypoint, position: [f64; 2]) -> Waypoint {
+    Waypoint {
+        // Transit waypoints take no photos so have an empty footprint
+        coverage_rect: CoverageRect {
+            coords: [position; 5],
+            center: position,
+        },
+        position,
+        zoom: None,
+        gsd: None,
+        terrain_elevation: None,
+        capture: false,
+        timelapse: None,
+        ..*template
+    }
+}
+
+/// Slows the waypoints near each end of a line linearly from the cruise speed down
+/// to the ramp's minimum speed at the end, where the aircraft turns
+fn apply_speed_ramp(waypoints: &mut [Waypoint], ramp: &SpeedRamp, cruise_speed: f64) {
+    let to_nztm = get_nztm_proj();
+    for line in waypoints.chunk_by_mut(|a, b| a.line == b.line) {
+        let mut along = vec![0.0];
+        for pair in line.windows(2) {
+            let (distance, _) = get_leg(pair[0].position, pair[1].position, to_nztm.as_ref());
+            along.push(along[along.len() - 1] + distance);
+        }
+        let length = along[along.len() - 1];
+
+        for (waypoint, distance) in line.iter_mut().zip(along) {
+            let to_end = distance.min(length - distance);
+            if to_end < ramp.distance_m {
+                let fraction = to_end / ramp.distance_m;
+                waypoint.speed = Some(ramp.min_speed + (cruise_speed - ramp.min_speed) * fraction);
+            }
+        }
+    }
+}
+
+/// Lowers any waypoint higher above the terrain than the maximum altitude and
+/// returns the indices of the waypoints that were lowered. Waypoints without terrain
+/// data are left as they are and counted in the warnings.
+fn enforce_max_altitude(
+    waypoints: &mut [Waypoint],
+    config: &MissionConfig,
+    warnings: &mut Vec<String>,
+) -> Vec<usize> {
+    let Some(ceiling) = config.max_altitude_agl else {
+        return Vec::new();
+    };
+    let Some(reference) = get_height_reference(config) else {
+        warnings.push("No home point set, the maximum altitude was not enforced".into());
+        return Vec::new();
+    };
+
+    let mut adjusted = Vec::new();
+    let mut unchecked = 0;
+    for (i, waypoint) in waypoints.iter_mut().enumerate() {
+        let Some(terrain) = waypoint.terrain_elevation else {
+            unchecked += 1;
+            continue;
+        };
+        let height = waypoint.altitude + reference - terrain;
+        if height > ceiling {
+            waypoint.altitude -= height - ceiling;
+            adjusted.push(i);
+        }
+    }
+    if unchecked > 0 {
+        warnings.push(format!(
+            "{} waypoints have no terrain data, their height under the maximum altitude was not checked",
+            unchecked
+        ));
+    }
+    adjusted
+}
+
+/// Checks the waypoint speeds and altitudes are within what the drone can fly, since
+/// DJI Pilot refuses missions that aren't
+fn check_drone_limits(
+    waypoints: &[Waypoint],
+    drone: &Drone,
+    config: &MissionConfig,
+) -> Result<(), FlightPathError> {
+    if let Some(max) = drone.speed_limit() {
+        if let Some(speed) = waypoints
+            .iter()
+            .filter_map(|w| w.speed)
+            .find(|speed| *speed > max)
+        {
+            return Err(FlightPathError::SpeedLimit {
+                speed,
+                max,
+                model: drone.model.clone(),
+            });
+        }
+    }
+
+    if let Some(max) = drone.altitude_limit() {
+        let reference = get_height_reference(config).unwrap_or(0.0);
+        if let Some(altitude) = waypoints
+            .iter()
+            .map(|w| w.altitude + reference)
+            .find(|altitude| *altitude > max)
+        {
+            return Err(FlightPathError::AltitudeLimit {
+                altitude,
+                max,
+                model: drone.model.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Resizes each photo footprint and GSD for the waypoint's actual height above the
+/// terrain once the altitudes are final. Footprints are drawn for the nominal layer
+/// altitude, but over a valley floor the camera is further from the ground and over
+/// a ridge closer, and both scale linearly with that distance. Waypoints without
+/// terrain data keep their nominal footprint.
+fn scale_footprints_to_agl(
+    waypoints: &mut [Waypoint],
+    layer_altitudes: &[f64],
+    config: &MissionConfig,
+) {
+    let Some(reference) = get_height_reference(config) else {
+        return;
+    };
+    for waypoint in waypoints.iter_mut().filter(|w| w.capture) {
+        let (Some(terrain), Some(&nominal)) = (
+            waypoint.terrain_elevation,
+            layer_altitudes.get(waypoint.layer),
+        ) else {
+            continue;
+        };
+        let agl = waypoint.altitude + reference - terrain;
+        if nominal <= 0.0 || agl <= 0.0 {
+            continue;
+        }
+
+        let scale = agl / nominal;
+        let center = waypoint.coverage_rect.center;
+        for corner in &mut waypoint.coverage_rect.coords {
+            *corner = [
+                center[0] + (corner[0] - center[0]) * scale,
+                center[1] + (corner[1] - center[1]) * scale,
+            ];
+        }
+        waypoint.gsd = waypoint.gsd.map(|gsd| gsd * scale);
+    }
+}
+
+/// Returns the elevation above mean sea level that the waypoint altitudes are measured
+/// from, or None if they are relative to a home point that hasn't been set
+fn get_height_reference(config: &MissionConfig) -> Option<f64> {
+    match (config.height_mode, config.home_point) {
+        // WGS84 altitudes are above the ellipsoid and the terrain above the geoid
+        (HeightMode::Wgs84, _) => Some(-config.geoid_offset_m.unwrap_or(0.0)),
+        (HeightMode::RelativeToStartPoint, home) => home.map(|home| home[2]),
+    }
+}
+
+/// Reverses the mission so it starts from the opposite end. Reversing a lawnmower
+/// reverses both the line order and the direction of each line, so it stays a
+/// valid zigzag. Lines are renumbered so line 0 is still the first one flown.
+fn reverse_mission(waypoints: &mut [Waypoint]) {
+    waypoints.reverse();
+    let last_line = waypoints.iter().map(|w| w.line).max().unwrap_or(0);
+    for waypoint in waypoints.iter_mut() {
+        waypoint.line = last_line - waypoint.line;
+    }
+}
+
+/// Sets the zoom ratio on the first waypoint of every pass so the camera is
+/// zoomed once at the start of each line rather than at every waypoint
+fn apply_pass_zoom(waypoints: &mut [Waypoint], zoom: f64) {
+    let mut previous_line = None;
+    for waypoint in waypoints.iter_mut() {
+        if previous_line != Some(waypoint.line) {
+            waypoint.zoom = Some(zoom);
+            previous_line = Some(waypoint.line);
+        }
+    }
+}
+
+/// Returns the ground sample distance in cm/pixel for a photo taken from
+/// `distance` meters away from the ground, or None if the drone has no camera specs
+pub(crate) fn calculate_gsd(drone: &Drone, distance: f64) -> Option<f64> {
+    let focal_length = drone.focal_length?;
+    let pixel_pitch = drone.pixel_pitch?;
+    // pitch (um) * distance (m) / focal length (mm) gives mm/pixel
+    Some(pixel_pitch * distance / focal_length / 10.0)
+}
+
+/// Returns the best and worst GSD across all waypoints
+fn get_gsd_range(waypoints: &[Waypoint]) -> (Option<f64>, Option<f64>) {
+    let gsds = waypoints.iter().filter(|w| w.capture).filter_map(|w| w.gsd);
+    let min = gsds.clone().reduce(f64::min);
+    let max = gsds.reduce(f64::max);
+    (min, max)
+}
+
+/// Calculates the search area of the polygon in square kilometers
+fn calculate_search_area(polygon: &Polygon, input_crs: &str) -> Result<f64, FlightPathError> {
+    // Convert polygon coordinates to meters (NZTM projection)
+    let coords_meters =
+        get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>(), input_crs)?;
+    let polygon_meters = Polygon::new(LineString::from(coords_meters), vec![]);
+
+    // Calculate area using the geo crate's Area trait
+    Ok(polygon_meters.unsigned_area() / 1_000_000.0)
+}
+
+/// Estimates the flight time in minutes, including any time spent hovering at waypoints.
+/// If a wind is configured, each leg is flown at the ground speed the wind allows.
+fn calculate_flight_time(waypoints: &[Waypoint], speed_ms: f64, config: &MissionConfig) -> f64 {
+    // The KMZ only hovers at the waypoints that take a photo
+    let dwell_time = config.hover_time * waypoints.iter().filter(|w| w.capture).count() as f64;
+    let total_time: f64 = get_leg_times(waypoints, speed_ms, config).iter().sum();
+
+    // Convert time from seconds to minutes
+    (total_time + dwell_time) / 60.0
+}
+
+/// Cuts the mission off at the last waypoint reached within `max_distance` meters of
+/// flying. Returns the number of waypoints removed and the share of the total
+/// distance they made up, or None if the whole mission is within the distance.
+fn truncate_to_distance(waypoints: &mut Vec<Waypoint>, max_distance: f64) -> Option<(usize, f64)> {
+    let legs = get_leg_distances(waypoints);
+    let total_distance: f64 = legs.iter().sum();
+    if total_distance <= max_distance {
+        return None;
+    }
+
+    let mut flown = 0.0;
+    let mut keep = 1;
+    for leg in legs {
+        if flown + leg > max_distance {
+            break;
+        }
+        flown += leg;
+        keep += 1;
+    }
+    let removed = waypoints.len() - keep;
+    waypoints.truncate(keep);
+    Some((removed, 1.0 - flown / total_distance))
+}
+
+/// Returns the distance in meters from each waypoint to the next
+pub(crate) fn get_leg_distances(waypoints: &[Waypoint]) -> Vec<f64> {
+    let to_nztm = get_nztm_proj();
+    waypoints
+        .windows(2)
+        .map(|pair| get_leg(pair[0].position, pair[1].position, to_nztm.as_ref()).0)
+        .collect()
+}
+
+/// Returns the time in seconds to fly from each waypoint to the next
+fn get_leg_times(waypoints: &[Waypoint], speed_ms: f64, config: &MissionConfig) -> Vec<f64> {
+    let to_nztm = get_nztm_proj();
+    waypoints
+        .windows(2)
+        .map(|pair| {
+            let (distance, track) = get_leg(pair[0].position, pair[1].position, to_nztm.as_ref());
+            let airspeed = pair[0].speed.unwrap_or(speed_ms);
+            let ground_speed = match &config.wind {
+                Some(wind) => wind.ground_speed(airspeed, track),
+                None => airspeed,
+            };
+            distance / ground_speed
+        })
+        .collect()
+}
+
+/// Returns the number of batteries needed to fly the mission. Each battery has to
+/// cover the transit out from the home point and the return to home as well as
+/// its share of the survey. Returns None if the transit alone exceeds the endurance.
+fn calculate_batteries_required(
+    waypoints: &[Waypoint],
+    flight_time: f64,
+    endurance: f64,
+    drone: &Drone,
+    config: &MissionConfig,
+) -> Option<usize> {
+    let survey_time_per_battery = get_survey_time_per_battery(waypoints, endurance, drone, config)?;
+    Some((flight_time / survey_time_per_battery).ceil().max(1.0) as usize)
+}
 
-    let waypoints =
-        get_waypoints_with_slope_adjustment(&polygon, &mbr, &heading_angle, &spacing, &vrt_path, &drone);
-    write_wqml(&waypoints, &heading_angle, &drone).await;
-    let search_area = calculate_search_area(&polygon);
-    let est_flight_time = calculate_flight_time(&waypoints, drone.speed);
+/// Returns the minutes of each battery left for surveying once the transit to and
+/// from the home point is taken off, or None if there is no time left
+fn get_survey_time_per_battery(
+    waypoints: &[Waypoint],
+    endurance: f64,
+    drone: &Drone,
+    config: &MissionConfig,
+) -> Option<f64> {
+    let transit_time = match (config.home_point, waypoints.first(), waypoints.last()) {
+        (Some(home), Some(first), Some(last)) => {
+            let home = [home[0], home[1]];
+            let to_nztm = get_nztm_proj();
+            let distance = get_distance_meters(home, first.position, to_nztm.as_ref())
+                + get_distance_meters(last.position, home, to_nztm.as_ref());
+            distance / drone.speed / 60.0
+        }
+        _ => 0.0,
+    };
 
-    FlightPlanResult {
-        waypoints,
-        heading_angle,
-        search_area,
-        est_flight_time,
+    let survey_time_per_battery = endurance - transit_time;
+    if survey_time_per_battery <= 0.0 {
+        println!("Warning: the survey area is out of range of a single battery");
+        return None;
     }
+    Some(survey_time_per_battery)
 }
 
-/// Calculates the search area of the polygon in square kilometers
-fn calculate_search_area(polygon: &Polygon) -> f64 {
-    // Convert polygon coordinates to meters (NZTM projection)
-    let coords_meters = get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>());
-    let polygon_meters = Polygon::new(LineString::from(coords_meters), vec![]);
+/// Splits the mission into consecutive runs of waypoints that can each be flown on
+/// one battery. Returns the index range and flight time in minutes of each run, or
+/// None if the survey area is out of range of a battery.
+fn get_battery_segments(
+    waypoints: &[Waypoint],
+    endurance: f64,
+    drone: &Drone,
+    config: &MissionConfig,
+) -> Option<Vec<(Range<usize>, f64)>> {
+    let budget = get_survey_time_per_battery(waypoints, endurance, drone, config)? * 60.0;
+
+    let dwell_time = |w: &Waypoint| if w.capture { config.hover_time } else { 0.0 };
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut time = waypoints.first().map_or(0.0, dwell_time);
+    for (i, leg_time) in get_leg_times(waypoints, drone.speed, config)
+        .into_iter()
+        .enumerate()
+    {
+        let next_time = time + leg_time + dwell_time(&waypoints[i + 1]);
+        if next_time > budget {
+            // Waypoint i + 1 starts the next battery
+            segments.push((start..i + 1, time / 60.0));
+            start = i + 1;
+            time = dwell_time(&waypoints[i + 1]);
+        } else {
+            time = next_time;
+        }
+    }
+    if start < waypoints.len() {
+        segments.push((start..waypoints.len(), time / 60.0));
+    }
+    Some(segments)
+}
 
-    // Calculate area using the geo crate's Area trait
-    polygon_meters.unsigned_area() / 1_000_000.0
+/// Returns the distance in meters between two WGS84 positions, measured in NZTM when
+/// the projection is given and along the great circle otherwise
+fn get_distance_meters(a: [f64; 2], b: [f64; 2], to_nztm: Option<&Proj>) -> f64 {
+    get_leg(a, b, to_nztm).0
 }
 
-fn calculate_flight_time(waypoints: &[Waypoint], speed_ms: f64) -> f64 {
-    if waypoints.len() < 2 {
-        return 0.0;
+/// Returns the WGS84 to NZTM projection, or None if PROJ isn't available so callers
+/// can fall back to great-circle math. The warning is only printed the first time.
+fn get_nztm_proj() -> Option<Proj> {
+    static PROJ_WARNING: Once = Once::new();
+    match Proj::new_known_crs(WGS84, NZTM, None) {
+        Ok(proj) => Some(proj),
+        Err(e) => {
+            PROJ_WARNING.call_once(|| {
+                println!(
+                    "Warning: PROJ unavailable ({}), using great-circle distances",
+                    e
+                )
+            });
+            None
+        }
     }
+}
 
-    let mut total_distance = 0.0;
-    let to_nztm =
-        Proj::new_known_crs("EPSG:4326", "EPSG:2193", None).expect("Failed to create projection");
+/// Returns the length in meters and the compass track in radians of the leg
+/// between two WGS84 points. Measured in NZTM when the projection is given,
+/// otherwise along the great circle.
+fn get_leg(a: [f64; 2], b: [f64; 2], to_nztm: Option<&Proj>) -> (f64, f64) {
+    if let Some(to_nztm) = to_nztm {
+        if let (Ok((x1, y1)), Ok((x2, y2))) =
+            (to_nztm.convert((a[0], a[1])), to_nztm.convert((b[0], b[1])))
+        {
+            return (
+                ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt(),
+                (x2 - x1).atan2(y2 - y1),
+            );
+        }
+    }
+    (haversine_distance(a, b), great_circle_bearing(a, b))
+}
 
-    for i in 0..waypoints.len() - 1 {
-        let current = waypoints[i];
-        let next = waypoints[i + 1];
+/// Great-circle distance in meters between two WGS84 points
+fn haversine_distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    let (lat1, lat2) = (a[1].to_radians(), b[1].to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b[0] - a[0]).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().asin()
+}
 
-        // Convert both points to meters
-        let (x1, y1) = to_nztm
-            .convert((current.position[0], current.position[1]))
-            .expect("Cannot convert current waypoint to NZTM");
-        let (x2, y2) = to_nztm
-            .convert((next.position[0], next.position[1]))
-            .expect("Cannot convert next waypoint to NZTM");
+/// Initial compass bearing in radians of the great circle from a to b
+fn great_circle_bearing(a: [f64; 2], b: [f64; 2]) -> f64 {
+    let (lat1, lat2) = (a[1].to_radians(), b[1].to_radians());
+    let dlon = (b[0] - a[0]).to_radians();
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    y.atan2(x)
+}
 
-        // Calculate distance between waypoints in meters
-        let distance = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt();
-        total_distance += distance;
+/// Calculate the slope magnitude at a given point
+pub(crate) fn calculate_slope_at_point(point: Coord, elevation: &dyn ElevationSource) -> f64 {
+    let sample_distance = elevation.pixel_size() * 2.0; // sample 2 pixels away
+
+    let centre = elevation.elevation_at(point.x, point.y);
+    let e_east = elevation.elevation_at(point.x + sample_distance, point.y);
+    let e_west = elevation.elevation_at(point.x - sample_distance, point.y);
+    let e_north = elevation.elevation_at(point.x, point.y + sample_distance);
+    let e_south = elevation.elevation_at(point.x, point.y - sample_distance);
+
+    // Calculate gradients, an axis with no data either side is taken as flat
+    let dx = axis_gradient(e_east, centre, e_west, sample_distance);
+    let dy = axis_gradient(e_north, centre, e_south, sample_distance);
+    if dx.is_none() && dy.is_none() {
+        return 0.0; // Return 0 slope if elevation data is unavailable
     }
 
-    // Convert time from seconds to minutes
-    (total_distance / speed_ms) / 60.0
+    // Calculate slope magnitude (in radians)
+    (dx.unwrap_or(0.0).powi(2) + dy.unwrap_or(0.0).powi(2))
+        .sqrt()
+        .atan()
 }
 
-/// Calculate the slope magnitude at a given point
-fn calculate_slope_at_point(
-    point: Coord,
-    rasterband: &gdal::raster::RasterBand,
-    geotransform: &[f64; 6],
-    raster_size: (usize, usize),
-) -> f64 {
-    let pixel_size = geotransform[1].abs(); // assuming square pixels
-    let sample_distance = pixel_size * 2.0; // sample 2 pixels away
-
-    // Get elevations in 4 directions
-    let elevations = [
-        get_elevation_at_point(
-            rasterband,
-            geotransform,
-            raster_size,
-            point.x + sample_distance,
-            point.y,
-        ),
-        get_elevation_at_point(
-            rasterband,
-            geotransform,
-            raster_size,
-            point.x - sample_distance,
-            point.y,
-        ),
-        get_elevation_at_point(
-            rasterband,
-            geotransform,
-            raster_size,
-            point.x,
-            point.y + sample_distance,
-        ),
-        get_elevation_at_point(
-            rasterband,
-            geotransform,
-            raster_size,
-            point.x,
-            point.y - sample_distance,
-        ),
-    ];
+/// Returns the gradient along one axis from the samples `distance` either side of
+/// the centre. Near the raster edge, where one side is off the dataset, it falls
+/// back to a one-sided difference against the centre.
+fn axis_gradient(
+    ahead: Option<f64>,
+    centre: Option<f64>,
+    behind: Option<f64>,
+    distance: f64,
+) -> Option<f64> {
+    match (ahead, centre, behind) {
+        (Some(ahead), _, Some(behind)) => Some((ahead - behind) / (2.0 * distance)),
+        (Some(ahead), Some(centre), None) => Some((ahead - centre) / distance),
+        (None, Some(centre), Some(behind)) => Some((centre - behind) / distance),
+        _ => None,
+    }
+}
 
-    // Calculate gradients
-    if let [Some(e_east), Some(e_west), Some(e_north), Some(e_south)] = elevations {
-        let dx = (e_east - e_west) / (2.0 * sample_distance);
-        let dy = (e_north - e_south) / (2.0 * sample_distance);
+/// Returns the mean slope angle in radians of the corridor between a flight line
+/// and the next one, `offset` away. The terrain is sampled at the start, middle and
+/// end of the line and at the matching points across the corridor. Returns 0 if
+/// there is no elevation data.
+fn calculate_corridor_slope(
+    line_points: &[Coord],
+    elevation: &dyn ElevationSource,
+    offset: Coord,
+) -> f64 {
+    let (Some(first), Some(last)) = (line_points.first(), line_points.last()) else {
+        return 0.0;
+    };
+    let middle = (*first + *last) / 2.0;
+    let width = (offset.x.powi(2) + offset.y.powi(2)).sqrt();
 
-        // Calculate slope magnitude (in radians)
-        (dx.powi(2) + dy.powi(2)).sqrt().atan()
+    let slopes: Vec<f64> = [*first, middle, *last]
+        .iter()
+        .filter_map(|point| {
+            let near = elevation.elevation_at(point.x, point.y)?;
+            let far = elevation.elevation_at(point.x + offset.x, point.y + offset.y)?;
+            Some(((far - near) / width).abs().atan())
+        })
+        .collect();
+    if slopes.is_empty() {
+        0.0
     } else {
-        0.0 // Return 0 slope if elevation data is unavailable
+        slopes.iter().sum::<f64>() / slopes.len() as f64
     }
 }
 
 /// Returns the coverage rectangle representing the area that the photo
 /// from that waypoint creates. Used for rendering the coverage rectangles on the frontend
-fn generate_coverage_rect(
+pub(crate) fn generate_coverage_rect(
     waypoint: &Coord,
     slope_magnitude: &f64,
     angle: &f64,
     drone: &Drone,
-) -> CoverageRect {
-    // TODO adjust photo height based on slope angle
-    let to_wgs84 =
-        Proj::new_known_crs("EPSG:2193", "EPSG:4326", None).expect("Failed to create projection");
-
+    to_wgs84: &Proj,
+) -> Result<CoverageRect, FlightPathError> {
+    // Drawn for the nominal height above ground, see scale_footprints_to_agl
     let base_coverage = get_ground_coverage(drone);
     let slope_adjusted_coverage = base_coverage / slope_magnitude.cos().max(0.1);
     let hw = base_coverage / 2.0;
@@ -197,13 +2089,11 @@ fn generate_coverage_rect(
     // project to WGS84
     let wgs84_coords: Vec<[f64; 2]> = rotated_corners
         .iter()
-        .map(|[x, y]| {
-            let (lon, lat) = to_wgs84.convert((*x, *y)).expect("Projection failed");
-            [lon, lat]
-        })
-        .collect();
+        .map(|[x, y]| to_wgs84.convert((*x, *y)).map(|(lon, lat)| [lon, lat]))
+        .collect::<Result<_, _>>()?;
+    let (lon, lat) = to_wgs84.convert((waypoint.x, waypoint.y))?;
 
-    CoverageRect {
+    Ok(CoverageRect {
         coords: [
             wgs84_coords[0],
             wgs84_coords[1],
@@ -211,58 +2101,61 @@ fn generate_coverage_rect(
             wgs84_coords[3],
             wgs84_coords[0],
         ],
-        center: {
-            let (lon, lat) = to_wgs84
-                .convert((waypoint.x, waypoint.y))
-                .expect("Projection failed");
-            [lon, lat]
-        },
-    }
+        center: [lon, lat],
+    })
 }
 
 /// Returns a grid of waypoints that cover the entire search area using a lawnmower pattern
-/// with slope adjustment applied to each waypoint as it's created
+/// with slope adjustment applied to each waypoint as it's created. Lines are
+/// `base_spacing` apart, and photos along them the configured waypoint spacing or
+/// otherwise the same distance apart on flat ground. Without elevation data the
+/// lines are laid out over flat terrain.
 fn get_waypoints_with_slope_adjustment(
     polygon: &Polygon,
-    mbr: &Polygon,
     angle: &f64,
     base_spacing: &f64,
-    vrt_path: &str,
+    elevation: Option<&dyn ElevationSource>,
     drone: &Drone,
-) -> Vec<Waypoint> {
+    config: &MissionConfig,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Waypoint>, FlightPathError> {
+    let input_crs = config.input_crs.as_str();
+    let mut photo_spacing = get_photo_spacing(drone, config, *base_spacing);
+    // Photos can't be closer together than the camera can take them at the flight speed
+    let shutter_spacing = drone
+        .min_shutter_interval_s
+        .map(|interval| interval * drone.speed);
+    if let Some(shutter_spacing) = shutter_spacing.filter(|s| photo_spacing < *s) {
+        warnings.push(format!(
+            "Photo spacing was widened from {:.1} m to {:.1} m so the camera can keep up at {:.1} m/s",
+            photo_spacing, shutter_spacing, drone.speed
+        ));
+        photo_spacing = shutter_spacing;
+    }
     let mut waypoints = Vec::new();
+    let anchor = get_anchor_meters(config)?;
+    let mbr = get_mbr(polygon);
     let mbr_coords = mbr.exterior().coords().collect::<Vec<_>>();
-    let mbr_coords_meters = get_coord_meters(&mbr_coords);
+    let mbr_coords_meters = get_coord_meters(&mbr_coords, input_crs)?;
 
     // Convert the search area polygon to meters
-    let search_coords_meters = get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>());
+    let search_coords_meters =
+        get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>(), input_crs)?;
     let search_polygon_meters = Polygon::new(LineString::from(search_coords_meters), vec![]);
 
-    // Setup elevation data access
-    let dataset = match Dataset::open(vrt_path) {
-        Ok(ds) => ds,
-        Err(_) => {
-            // Fallback to original method without slope adjustment
-            return get_waypoints_fallback(polygon, mbr, angle, base_spacing, drone);
-        }
-    };
-
-    let rasterband = match dataset.rasterband(1) {
-        Ok(band) => band,
-        Err(_) => {
-            return get_waypoints_fallback(polygon, mbr, angle, base_spacing, drone);
-        }
+    let Some(elevation) = elevation else {
+        // Fallback to original method without slope adjustment
+        return get_waypoints_fallback(
+            polygon,
+            angle,
+            base_spacing,
+            &photo_spacing,
+            anchor,
+            drone,
+            input_crs,
+        );
     };
 
-    let geotransform = match dataset.geo_transform() {
-        Ok(gt) => gt,
-        Err(_) => {
-            return get_waypoints_fallback(polygon, mbr, angle, base_spacing, drone);
-        }
-    };
-
-    let raster_size = dataset.raster_size();
-
     // Find the bounds of the MBR
     let min_x = mbr_coords_meters
         .iter()
@@ -290,17 +2183,36 @@ fn get_waypoints_with_slope_adjustment(
     let flight_dx = angle.cos();
     let flight_dy = angle.sin();
 
-    // Calculate the number of parallel lines needed (using base spacing)
+    // Width of the MBR across any line direction is at most its diagonal
     let width = ((max_x - min_x).powi(2) + (max_y - min_y).powi(2)).sqrt();
-    let num_lines = (width / base_spacing).ceil() as i32;
+    let half_width = width / 2.0;
 
-    let to_wgs84 =
-        Proj::new_known_crs("EPSG:2193", "EPSG:4326", None).expect("Failed to create projection");
+    let to_wgs84 = get_projection(NZTM, WGS84)?;
 
-    // Generate waypoints for each flight line
+    // Generate waypoints for each flight line. The lines are spaced out from one
+    // side so each gap can be narrowed for the cross-slope of the line before it.
     let mut line_index = 0;
-    for i in -(num_lines / 2)..=(num_lines / 2) {
-        let offset_dist = i as f64 * base_spacing;
+    let mut floored_photos = 0;
+    let mut shutter_limited_photos = 0;
+    // An anchor shifts the lines across so one of them passes through it
+    let center = Coord {
+        x: (min_x + max_x) / 2.0,
+        y: (min_y + max_y) / 2.0,
+    };
+    let line_phase = get_anchor_phase(center, anchor, (line_dx, line_dy), *base_spacing);
+    // Start on or beyond the near edge, and keep adding lines until one reaches the far
+    // edge, so both edges are covered whatever the width and spacing
+    let mut offset_dist =
+        line_phase - ((half_width + line_phase) / base_spacing).ceil() * base_spacing;
+    // Anchored lines skip outside the area a whole photo spacing at a time so the
+    // first photo inside stays in phase with the anchor
+    let outside_steps = if anchor.is_some() {
+        LINE_STEPS_PER_PHOTO
+    } else {
+        LINE_STEPS_PER_PHOTO / 4
+    };
+    loop {
+        let mut line_points = Vec::new();
 
         // Calculate the center point of the MBR
         let center_x = (min_x + max_x) / 2.0;
@@ -318,10 +2230,21 @@ fn get_waypoints_with_slope_adjustment(
         let start_point_x = line_start_x - (line_length / 2.0) * flight_dx;
         let start_point_y = line_start_y - (line_length / 2.0) * flight_dy;
 
-        let mut current_distance = 0.0;
-        let mut waypoint_count = 0;
+        // Positions are a whole number of steps from the start of the line rather than
+        // a running sum, so identical inputs always give identical waypoints
+        let step = photo_spacing / LINE_STEPS_PER_PHOTO as f64;
+        let steps = (line_length / step).ceil() as usize;
+        let mut step_index = 0;
+        let mut offset_line = config.grid_offset && line_index % 2 == 1;
+        let start_point = Coord {
+            x: start_point_x,
+            y: start_point_y,
+        };
+        let along_phase =
+            get_anchor_phase(start_point, anchor, (flight_dx, flight_dy), photo_spacing);
 
-        while current_distance < line_length {
+        while step_index < steps {
+            let current_distance = along_phase + step_index as f64 * step;
             let point_x = start_point_x + current_distance * flight_dx;
             let point_y = start_point_y + current_distance * flight_dy;
 
@@ -331,24 +2254,22 @@ fn get_waypoints_with_slope_adjustment(
             };
 
             // Check if this point is within the search area
-            if search_polygon_meters.coordinate_position(&point) == CoordPos::Inside
-                || search_polygon_meters.coordinate_position(&point) == CoordPos::OnBoundary
-            {
+            let inside = search_polygon_meters.coordinate_position(&point) != CoordPos::Outside;
+            if inside && offset_line {
+                // Start offset lines half a spacing into the area, so their photos sit
+                // between the photos on the neighbouring lines
+                offset_line = false;
+                step_index += LINE_STEPS_PER_PHOTO / 2;
+            } else if inside {
                 // Calculate slope at this point
-                let slope_angle =
-                    calculate_slope_at_point(point, &rasterband, &geotransform, raster_size);
+                let slope_angle = calculate_slope_at_point(point, elevation);
+                line_points.push(point);
 
                 let coverage_rect =
-                    generate_coverage_rect(&point, &slope_angle, &perp_angle, drone);
+                    generate_coverage_rect(&point, &slope_angle, &perp_angle, drone, &to_wgs84)?;
 
                 // Apply slope adjustment to this waypoint position
-                let adjusted_point = adjust_waypoint_for_slope(
-                    point,
-                    &rasterband,
-                    &geotransform,
-                    raster_size,
-                    drone.altitude,
-                );
+                let adjusted_point = adjust_waypoint_for_slope(point, elevation, drone.altitude);
 
                 // Convert adjusted waypoint back to lat/lon
                 if let Ok((lon, lat)) = to_wgs84.convert((adjusted_point.x, adjusted_point.y)) {
@@ -356,7 +2277,20 @@ fn get_waypoints_with_slope_adjustment(
                         coverage_rect,
                         position: [lon, lat],
                         bearing: 0.0,
-                        altitude: 100.0,
+                        altitude: drone.altitude,
+                        line: line_index,
+                        zoom: None,
+                        // The camera points along the slope normal, so the distance
+                        // to the ground grows as the slope steepens
+                        gsd: calculate_gsd(drone, drone.altitude / slope_angle.cos().max(0.1)),
+                        terrain_elevation: elevation
+                            .elevation_at(adjusted_point.x, adjusted_point.y),
+                        capture: true,
+                        layer: 0,
+                        gimbal_pitch: NADIR_PITCH,
+                        speed: None,
+                        heading: None,
+                        timelapse: None,
                     });
                 }
 
@@ -364,18 +2298,25 @@ fn get_waypoints_with_slope_adjustment(
                 // When slope increases, effective coverage width decreases by cos(slope)
                 // So we need to reduce spacing to maintain overlap
                 let slope_factor = slope_angle.cos().max(0.1); // Prevent division by very small numbers
-                let adjusted_spacing = base_spacing * slope_factor;
+                let mut adjusted_spacing = photo_spacing * slope_factor;
+                if let Some(min_spacing) = config.min_photo_spacing_m {
+                    if adjusted_spacing < min_spacing {
+                        adjusted_spacing = min_spacing;
+                        floored_photos += 1;
+                    }
+                }
+                if let Some(shutter_spacing) = shutter_spacing {
+                    if adjusted_spacing < shutter_spacing {
+                        adjusted_spacing = shutter_spacing;
+                        shutter_limited_photos += 1;
+                    }
+                }
 
-                current_distance += adjusted_spacing;
+                // Rounded to the nearest step, within 2.5% of the photo spacing
+                step_index += ((adjusted_spacing / step).round() as usize).max(1);
             } else {
                 // Move forward by a small increment if outside search area
-                current_distance += base_spacing / 4.0;
-            }
-
-            waypoint_count += 1;
-            // Safety check to prevent infinite loops
-            if waypoint_count > 10000 {
-                break;
+                step_index += outside_steps;
             }
         }
 
@@ -388,25 +2329,101 @@ fn get_waypoints_with_slope_adjustment(
             }
             line_index += 1;
         }
+
+        // On a cross-slope the footprint covers cos(slope) as much ground across the
+        // line as it does on the flat, so the gap to the next line is narrowed by the
+        // same factor to keep the sidelap
+        let cross_slope = calculate_corridor_slope(
+            &line_points,
+            elevation,
+            Coord {
+                x: line_dx,
+                y: line_dy,
+            } * *base_spacing,
+        );
+        if offset_dist >= half_width {
+            break;
+        }
+        offset_dist += base_spacing * cross_slope.cos().max(0.1);
     }
 
-    waypoints
+    if let (true, Some(min_spacing)) = (floored_photos > 0, config.min_photo_spacing_m) {
+        warnings.push(format!(
+            "Photo spacing was held at the {:.1} m minimum for {} photos on steep terrain, overlap is reduced there",
+            min_spacing, floored_photos
+        ));
+    }
+    if let (true, Some(shutter_spacing)) = (shutter_limited_photos > 0, shutter_spacing) {
+        warnings.push(format!(
+            "{} photos on steep terrain were spaced {:.1} m apart so the camera can keep up, overlap is reduced there",
+            shutter_limited_photos, shutter_spacing
+        ));
+    }
+
+    Ok(waypoints)
 }
 
 /// Fallback waypoint generation without slope adjustment
 fn get_waypoints_fallback(
     polygon: &Polygon,
-    mbr: &Polygon,
     angle: &f64,
     spacing: &f64,
+    photo_spacing: &f64,
+    anchor: Option<Coord>,
     drone: &Drone,
-) -> Vec<Waypoint> {
+    input_crs: &str,
+) -> Result<Vec<Waypoint>, FlightPathError> {
+    let perp_angle = angle + std::f64::consts::PI / 2.0;
+    let points = get_fallback_points(polygon, angle, spacing, photo_spacing, anchor, input_crs)?;
+
+    // Convert waypoints back to lat/lon
+    let mut waypoints_latlon = Vec::new();
+    let to_wgs84 = get_projection(NZTM, WGS84)?;
+
+    for (coord, line) in points {
+        let coverage_rect = generate_coverage_rect(&coord, &0.0, &perp_angle, drone, &to_wgs84)?;
+        let (x, y) = to_wgs84.convert((coord.x, coord.y))?;
+        waypoints_latlon.push(Waypoint {
+            coverage_rect,
+            position: [x, y],
+            bearing: 0.0,
+            altitude: drone.altitude,
+            line,
+            zoom: None,
+            gsd: calculate_gsd(drone, drone.altitude),
+            terrain_elevation: None,
+            capture: true,
+            layer: 0,
+            gimbal_pitch: NADIR_PITCH,
+            speed: None,
+            heading: None,
+            timelapse: None,
+        });
+    }
+
+    Ok(waypoints_latlon)
+}
+
+/// Returns the NZTM positions and line indices of a flat-terrain lawnmower grid
+/// over the search area, before any waypoints are built from them. Lines are
+/// `spacing` apart and the points along them `photo_spacing` apart, phased to pass
+/// through the NZTM `anchor` if one is given.
+fn get_fallback_points(
+    polygon: &Polygon,
+    angle: &f64,
+    spacing: &f64,
+    photo_spacing: &f64,
+    anchor: Option<Coord>,
+    input_crs: &str,
+) -> Result<Vec<(Coord, usize)>, FlightPathError> {
     let mut waypoints = Vec::new();
+    let mbr = get_mbr(polygon);
     let mbr_coords = mbr.exterior().coords().collect::<Vec<_>>();
-    let mbr_coords_meters = get_coord_meters(&mbr_coords);
+    let mbr_coords_meters = get_coord_meters(&mbr_coords, input_crs)?;
 
     // Convert the search area polygon to meters
-    let search_coords_meters = get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>());
+    let search_coords_meters =
+        get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>(), input_crs)?;
     let search_polygon_meters = Polygon::new(LineString::from(search_coords_meters), vec![]);
 
     // Find the bounds of the MBR
@@ -436,14 +2453,26 @@ fn get_waypoints_fallback(
     let flight_dx = angle.cos();
     let flight_dy = angle.sin();
 
-    // Calculate the number of parallel lines needed
+    // Width of the MBR across any line direction is at most its diagonal
     let width = ((max_x - min_x).powi(2) + (max_y - min_y).powi(2)).sqrt();
-    let num_lines = (width / spacing).ceil() as i32;
+    let half_width = width / 2.0;
+
+    // An anchor shifts the lines across so one of them passes through it
+    let center = Coord {
+        x: (min_x + max_x) / 2.0,
+        y: (min_y + max_y) / 2.0,
+    };
+    let line_phase = get_anchor_phase(center, anchor, (line_dx, line_dy), *spacing);
+
+    // Lines from on or beyond the near edge to on or beyond the far edge, so both
+    // edges are covered whether or not the width is a whole number of spacings
+    let first_line = -((half_width + line_phase) / spacing).ceil() as i32;
+    let last_line = ((half_width - line_phase) / spacing).ceil() as i32;
 
     // Generate waypoints for each flight line
     let mut line_index = 0;
-    for i in -(num_lines / 2)..=(num_lines / 2) {
-        let offset_dist = i as f64 * spacing;
+    for i in first_line..=last_line {
+        let offset_dist = i as f64 * spacing + line_phase;
 
         // Calculate the center point of the MBR
         let center_x = (min_x + max_x) / 2.0;
@@ -453,105 +2482,80 @@ fn get_waypoints_fallback(
         let line_start_x = center_x + offset_dist * line_dx;
         let line_start_y = center_y + offset_dist * line_dy;
 
-        // Generate points along this flight line
+        // Clip a line long enough to cross the whole area to the parts inside it,
+        // each pointing in the direction of flight and sorted along it
+        let flight_line = LineString::from(vec![
+            (
+                line_start_x - width * flight_dx,
+                line_start_y - width * flight_dy,
+            ),
+            (
+                line_start_x + width * flight_dx,
+                line_start_y + width * flight_dy,
+            ),
+        ]);
+        let along = |p: Coord| p.x * flight_dx + p.y * flight_dy;
+        let mut segments: Vec<(Coord, Coord)> = search_polygon_meters
+            .clip(&MultiLineString::new(vec![flight_line]), false)
+            .into_iter()
+            .filter_map(|segment| Some((*segment.0.first()?, *segment.0.last()?)))
+            .map(|(a, b)| if along(b) < along(a) { (b, a) } else { (a, b) })
+            .collect();
+        segments.sort_by(|a, b| along(a.0).total_cmp(&along(b.0)));
+
+        // Place a waypoint every photo spacing along each part
         let mut line_waypoints = Vec::new();
-        let line_length = width * 2.0; // Make sure we cover the entire area
-        let num_points = (line_length / (spacing / 4.0)) as i32; // Higher resolution along the line
-
-        for j in -(num_points / 2)..=(num_points / 2) {
-            let point_dist = j as f64 * (spacing / 4.0);
-            let point_x = line_start_x + point_dist * flight_dx;
-            let point_y = line_start_y + point_dist * flight_dy;
-
-            let point = Coord {
-                x: point_x,
-                y: point_y,
-            };
-
-            // Check if this point is within the search area
-            if search_polygon_meters.coordinate_position(&point) == CoordPos::Inside
-                || search_polygon_meters.coordinate_position(&point) == CoordPos::OnBoundary
-            {
-                line_waypoints.push(point);
+        for (start, end) in segments {
+            let length = along(end) - along(start);
+            // Anchored photos sit a whole photo spacing along the line from the anchor
+            let first = anchor.map_or(0.0, |anchor| {
+                (along(anchor) - along(start)).rem_euclid(*photo_spacing)
+            });
+            if first > length {
+                continue;
+            }
+            let count = ((length - first) / photo_spacing).floor() as usize + 1;
+            for k in 0..count {
+                let distance = first + k as f64 * photo_spacing;
+                line_waypoints.push(Coord {
+                    x: start.x + distance * flight_dx,
+                    y: start.y + distance * flight_dy,
+                });
             }
         }
 
         // Add waypoints from this line (alternate direction for lawnmower pattern)
         if !line_waypoints.is_empty() {
+            let line_waypoints = line_waypoints.into_iter().map(move |p| (p, line_index));
             if line_index % 2 == 0 {
                 waypoints.extend(line_waypoints);
             } else {
-                waypoints.extend(line_waypoints.into_iter().rev());
+                waypoints.extend(line_waypoints.rev());
             }
             line_index += 1;
         }
     }
 
-    // Convert waypoints back to lat/lon
-    let mut waypoints_latlon = Vec::new();
-    let to_wgs84 =
-        Proj::new_known_crs("EPSG:2193", "EPSG:4326", None).expect("Failed to create projection");
-
-    for coord in waypoints {
-        let coverage_rect = generate_coverage_rect(&coord, &0.0, &perp_angle, drone);
-        let (x, y) = to_wgs84
-            .convert((coord.x, coord.y))
-            .expect("Cannot convert coords to wgs84");
-        waypoints_latlon.push(Waypoint {
-            coverage_rect,
-            position: [x, y],
-            bearing: 0.0,
-            altitude: 100.0,
-        });
-    }
-
-    waypoints_latlon
+    Ok(waypoints)
 }
 
-fn adjust_waypoint_for_slope(
+pub(crate) fn adjust_waypoint_for_slope(
     waypoint: Coord,
-    rasterband: &gdal::raster::RasterBand,
-    geotransform: &[f64; 6],
-    raster_size: (usize, usize),
+    elevation: &dyn ElevationSource,
     altitude: f64,
 ) -> Coord {
     let x = waypoint.x;
     let y = waypoint.y;
 
     // Calculate slope using finite differences
-    let pixel_size = geotransform[1].abs(); // assuming square pixels
-    let sample_distance = pixel_size * 2.0; // sample 2 pixels away
+    let sample_distance = elevation.pixel_size() * 2.0; // sample 2 pixels away
 
     // Get elevations in 4 directions
     let elevations = [
-        get_elevation_at_point(
-            rasterband,
-            geotransform,
-            raster_size,
-            x + sample_distance,
-            y,
-        ),
-        get_elevation_at_point(
-            rasterband,
-            geotransform,
-            raster_size,
-            x - sample_distance,
-            y,
-        ),
-        get_elevation_at_point(
-            rasterband,
-            geotransform,
-            raster_size,
-            x,
-            y + sample_distance,
-        ),
-        get_elevation_at_point(
-            rasterband,
-            geotransform,
-            raster_size,
-            x,
-            y - sample_distance,
-        ),
+        elevation.elevation_at(x + sample_distance, y),
+        elevation.elevation_at(x - sample_distance, y),
+        elevation.elevation_at(x, y + sample_distance),
+        elevation.elevation_at(x, y - sample_distance),
     ];
 
     if let [Some(e_east), Some(e_west), Some(e_north), Some(e_south)] = elevations {
@@ -597,88 +2601,838 @@ fn adjust_waypoint_for_slope(
     }
 }
 
-fn get_elevation_at_point(
-    rasterband: &gdal::raster::RasterBand,
-    geotransform: &[f64; 6],
-    raster_size: (usize, usize),
-    x: f64,
-    y: f64,
-) -> Option<f64> {
-    let pixel_x = ((x - geotransform[0]) / geotransform[1]).floor() as isize;
-    let pixel_y = ((y - geotransform[3]) / geotransform[5]).floor() as isize;
+/// Returns the configured grid anchor in NZTM meters, if there is one
+fn get_anchor_meters(config: &MissionConfig) -> Result<Option<Coord>, FlightPathError> {
+    let Some(anchor) = config.anchor.map(|[x, y]| Coord { x, y }) else {
+        return Ok(None);
+    };
+    Ok(get_coord_meters(&[&anchor], &config.input_crs)?
+        .first()
+        .copied())
+}
 
-    if pixel_x < 0
-        || pixel_y < 0
-        || pixel_x >= raster_size.0 as isize
-        || pixel_y >= raster_size.1 as isize
-    {
-        return None;
-    }
+/// Returns how far to move a grid origin along the unit `direction` so that points a
+/// whole number of `spacing` from it line up with the anchor. 0 without an anchor.
+fn get_anchor_phase(
+    origin: Coord,
+    anchor: Option<Coord>,
+    direction: (f64, f64),
+    spacing: f64,
+) -> f64 {
+    anchor.map_or(0.0, |anchor| {
+        let distance = (anchor.x - origin.x) * direction.0 + (anchor.y - origin.y) * direction.1;
+        distance.rem_euclid(spacing)
+    })
+}
 
-    let mut buffer = [0.0f32; 1];
-    match rasterband.read_into_slice::<f32>((pixel_x, pixel_y), (1, 1), (1, 1), &mut buffer, None) {
-        Ok(_) => {
-            let elevation = buffer[0] as f64;
-            if (elevation - (-32767.0)).abs() < 0.1 {
-                None
-            } else {
-                Some(elevation)
-            }
-        }
-        Err(_) => None,
+/// Returns the distance in meters between flight lines for the quality preset's side
+/// overlap, or the drone's overlap without one
+pub(crate) fn get_line_spacing(drone: &Drone, quality: Option<Quality>) -> f64 {
+    let overlap = quality.map_or(drone.overlap, |q| q.overlaps().1);
+    get_overlap_spacing(drone, overlap)
+}
+
+/// Returns the distance in meters between photos along a line, from the configured
+/// waypoint spacing, then the quality preset's front overlap, then the line spacing
+fn get_photo_spacing(drone: &Drone, config: &MissionConfig, line_spacing: f64) -> f64 {
+    match (config.waypoint_spacing_m, config.quality) {
+        (Some(spacing), _) => spacing,
+        (None, Some(quality)) => get_overlap_spacing(drone, quality.overlaps().0),
+        (None, None) => line_spacing,
     }
 }
 
+/// Returns the distance in meters between photos that overlap by `overlap` percent
+fn get_overlap_spacing(drone: &Drone, overlap: f64) -> f64 {
+    get_ground_coverage(drone) * (100.0 - overlap) / 100.0
+}
+
 /// Returns the ground coverage in meters of a photo taken from the drone
-fn get_ground_coverage(drone: &Drone) -> f64 {
+pub(crate) fn get_ground_coverage(drone: &Drone) -> f64 {
     let fov_rad = drone.fov.to_radians();
     2.0 * drone.altitude * (fov_rad / 2.0).tan()
 }
 
-/// Convert Vec of coords in lat, lon to meters
-fn get_coord_meters(coords: &[&Coord]) -> Vec<Coord> {
-    let mut converted = Vec::new();
-    let to_nztm =
-        Proj::new_known_crs("EPSG:4326", "EPSG:2193", None).expect("Failed to create projection");
-    for coord in coords {
-        let (x, y) = to_nztm
-            .convert((coord.x, coord.y))
-            .expect("Cannot convert coords to nztm");
+/// Convert Vec of coords in the input CRS to meters
+pub(crate) fn get_coord_meters(
+    coords: &[&Coord],
+    input_crs: &str,
+) -> Result<Vec<Coord>, FlightPathError> {
+    if input_crs == NZTM {
+        return Ok(coords.iter().map(|c| **c).collect());
+    }
+    project_coords(coords, &get_projection(input_crs, NZTM)?)
+}
+
+/// Returns the projection from one CRS to another, or an error if PROJ can't
+/// create it, such as when PROJ isn't installed
+pub(crate) fn get_projection(from: &str, to: &str) -> Result<Proj, FlightPathError> {
+    Proj::new_known_crs(from, to, None).map_err(|e| {
+        FlightPathError::Projection(format!("cannot project {} to {}: {}", from, to, e))
+    })
+}
+
+/// Converts every coord with the projection, so one projection can be reused for
+/// many conversions
+pub(crate) fn project_coords(
+    coords: &[&Coord],
+    proj: &Proj,
+) -> Result<Vec<Coord>, FlightPathError> {
+    coords
+        .iter()
+        .map(|c| {
+            proj.convert((c.x, c.y))
+                .map(Coord::from)
+                .map_err(FlightPathError::from)
+        })
+        .collect()
+}
+
+/// Grows the polygon outwards by `distance` meters, or shrinks it if negative. The
+/// buffer is worked out in NZTM and returned in the input CRS. Returns None if a
+/// negative buffer leaves nothing of the polygon.
+fn buffer_polygon(
+    polygon: &Polygon,
+    distance: f64,
+    input_crs: &str,
+) -> Result<Option<Polygon>, FlightPathError> {
+    let coords_meters =
+        get_coord_meters(&polygon.exterior().coords().collect::<Vec<_>>(), input_crs)?;
+    let polygon_meters = Polygon::new(LineString::from(coords_meters), vec![]);
+
+    // Shrinking a narrow area can split it, so keep the largest part
+    let Some(buffered) = polygon_meters
+        .buffer(distance)
+        .into_iter()
+        .max_by(|a, b| a.unsigned_area().total_cmp(&b.unsigned_area()))
+    else {
+        return Ok(None);
+    };
+    if input_crs == NZTM {
+        return Ok(Some(buffered));
+    }
+
+    let from_nztm = get_projection(NZTM, input_crs)?;
+    let coords = project_coords(
+        &buffered.exterior().coords().collect::<Vec<_>>(),
+        &from_nztm,
+    )?;
+    Ok(Some(Polygon::new(LineString::from(coords), vec![])))
+}
+
+/// Returns which waypoints are inside the keep-in boundary, given in the input CRS.
+/// Waypoints on the boundary count as inside.
+fn inside_keep_in(
+    waypoints: &[Waypoint],
+    keep_in: &Polygon,
+    input_crs: &str,
+) -> Result<Vec<bool>, FlightPathError> {
+    let keep_in = if input_crs == WGS84 {
+        keep_in.clone()
+    } else {
+        let to_wgs84 = get_projection(input_crs, WGS84)?;
+        let coords: Vec<Coord> = keep_in
+            .exterior()
+            .coords()
+            .filter_map(|c| to_wgs84.convert((c.x, c.y)).ok().map(Coord::from))
+            .collect();
+        Polygon::new(LineString::from(coords), vec![])
+    };
+
+    Ok(waypoints
+        .iter()
+        .map(|w| {
+            let position = Coord::from((w.position[0], w.position[1]));
+            keep_in.coordinate_position(&position) != CoordPos::Outside
+        })
+        .collect())
+}
+
+/// Returns the centroid of the polygon in WGS84
+fn get_wgs84_centroid(polygon: &Polygon, input_crs: &str) -> Option<Coord> {
+    let centroid = polygon.centroid()?;
+    if input_crs == WGS84 {
+        return Some(centroid.0);
+    }
+
+    let to_wgs84 = get_projection(input_crs, WGS84).ok()?;
+    let (lon, lat) = to_wgs84.convert((centroid.x(), centroid.y())).ok()?;
+    Some(Coord { x: lon, y: lat })
+}
+
+/// Returns the minimum rotated rectangle of the polygon. Degenerate polygons (slivers
+/// or zero area shapes) fall back to the axis aligned bounding rectangle.
+fn get_mbr(polygon: &Polygon) -> Polygon {
+    if let Some(mbr) = MinimumRotatedRect::minimum_rotated_rect(polygon) {
+        return mbr;
+    }
 
-        converted.push(Coord { x, y });
+    println!("Warning: could not compute minimum rotated rectangle, using bounding rectangle");
+    match polygon.bounding_rect() {
+        Some(rect) => rect.to_polygon(),
+        None => Polygon::new(LineString::new(vec![]), vec![]),
     }
-    converted
+}
+
+/// Converts a planar math angle in radians, anticlockwise from east, to a compass
+/// bearing in degrees clockwise from north, in 0 to 360. East is 90° and north 0°.
+pub(crate) fn math_angle_to_compass_bearing(angle: f64) -> f64 {
+    (90.0 - angle.to_degrees()).rem_euclid(360.0)
+}
+
+/// Converts a compass bearing in degrees clockwise from north to a planar math angle
+/// in radians anticlockwise from east, the inverse of math_angle_to_compass_bearing
+pub(crate) fn compass_bearing_to_math_angle(bearing: f64) -> f64 {
+    (90.0 - bearing).to_radians()
 }
 
 /// Returns the optimal angle of the lawnmover pattern based on the minimum rotated
-/// rectangle of the search area.
-fn get_lawnmower_angle(mbr_coords: &[&Coord]) -> f64 {
-    let to_nztm =
-        Proj::new_known_crs("EPSG:4326", "EPSG:2193", None).expect("Failed to create projection");
+/// rectangle of the search area. This is a planar math angle in radians, measured
+/// anticlockwise from grid east in NZTM, not a compass bearing. A rectangle with its
+/// long axis running east-west gives about 0 (or ±pi), one running north-south about
+/// ±pi/2. Use math_angle_to_compass_bearing for the compass bearing of the lines.
+fn get_lawnmower_angle(mbr_coords: &[&Coord], input_crs: &str) -> Result<f64, FlightPathError> {
+    // Length and angle of each edge. The angle is only needed to within a degree or
+    // so, which great-circle math manages when PROJ isn't available.
+    let edges: Vec<(f64, f64)> = if input_crs == WGS84 && get_nztm_proj().is_none() {
+        mbr_coords
+            .windows(2)
+            .map(|edge| {
+                let (a, b) = ([edge[0].x, edge[0].y], [edge[1].x, edge[1].y]);
+                let bearing = great_circle_bearing(a, b).to_degrees();
+                (
+                    haversine_distance(a, b),
+                    compass_bearing_to_math_angle(bearing),
+                )
+            })
+            .collect()
+    } else {
+        get_coord_meters(mbr_coords, input_crs)?
+            .windows(2)
+            .map(|edge| {
+                let dx = edge[1].x - edge[0].x;
+                let dy = edge[1].y - edge[0].y;
+                ((dx * dx + dy * dy).sqrt(), dy.atan2(dx))
+            })
+            .collect()
+    };
+
+    // Fly along the longest edge
+    Ok(edges
+        .into_iter()
+        .fold(
+            (0.0, 0.0),
+            |longest, edge| {
+                if edge.0 > longest.0 {
+                    edge
+                } else {
+                    longest
+                }
+            },
+        )
+        .1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elevation::SyntheticElevationSource;
+    use serde_json::json;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn drone() -> Drone {
+        serde_json::from_value(json!({
+            "model": "M30T",
+            "fov": 84.0,
+            "altitude": 100.0,
+            "overlap": 70.0,
+            "speed": 10.0,
+        }))
+        .unwrap()
+    }
+
+    /// Survey photo waypoint at the WGS84 position, 100 m up
+    fn waypoint(position: [f64; 2]) -> Waypoint {
+        Waypoint {
+            coverage_rect: CoverageRect {
+                coords: [position; 5],
+                center: position,
+            },
+            position,
+            bearing: 0.0,
+            altitude: 100.0,
+            line: 0,
+            zoom: None,
+            gsd: None,
+            terrain_elevation: None,
+            capture: true,
+            layer: 0,
+            gimbal_pitch: NADIR_PITCH,
+            speed: None,
+            heading: None,
+            timelapse: None,
+        }
+    }
+
+    #[test]
+    fn slope_at_point_matches_plane() {
+        // A 3-4-5 gradient rises 0.5 m per meter down the steepest direction
+        let plane = SyntheticElevationSource::plane((0.3, 0.4));
+        let slope = calculate_slope_at_point(Coord { x: 10.0, y: -20.0 }, &plane);
+        assert!((slope - 0.5_f64.atan()).abs() < TOLERANCE);
+
+        let flat = SyntheticElevationSource::plane((0.0, 0.0));
+        assert_eq!(
+            calculate_slope_at_point(Coord { x: 0.0, y: 0.0 }, &flat),
+            0.0
+        );
+    }
+
+    #[test]
+    fn slope_adjustment_moves_waypoint_downhill() {
+        let plane = SyntheticElevationSource::plane((0.3, 0.4));
+        let waypoint = Coord { x: 10.0, y: 10.0 };
+        let adjusted = adjust_waypoint_for_slope(waypoint, &plane, 100.0);
+
+        // Moved along the plane normal, altitude * tan(slope) = 50 m downhill
+        assert!((adjusted.x - (10.0 - 30.0)).abs() < TOLERANCE);
+        assert!((adjusted.y - (10.0 - 40.0)).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn slope_adjustment_leaves_flat_terrain() {
+        let flat = SyntheticElevationSource::plane((0.0, 0.0));
+        let waypoint = Coord { x: 10.0, y: 10.0 };
+        assert_eq!(adjust_waypoint_for_slope(waypoint, &flat, 100.0), waypoint);
+    }
+
+    /// Closed ring of a `length` by `width` rectangle in NZTM, its long side at
+    /// `angle` radians anticlockwise from grid east
+    fn rectangle(length: f64, width: f64, angle: f64) -> Vec<Coord> {
+        let origin = Coord {
+            x: 1_750_000.0,
+            y: 5_900_000.0,
+        };
+        let (sin, cos) = angle.sin_cos();
+        [
+            (0.0, 0.0),
+            (length, 0.0),
+            (length, width),
+            (0.0, width),
+            (0.0, 0.0),
+        ]
+        .iter()
+        .map(|&(u, v)| Coord {
+            x: origin.x + u * cos - v * sin,
+            y: origin.y + u * sin + v * cos,
+        })
+        .collect()
+    }
 
-    let mut max_dist = 0.0;
-    let mut longest_len_dx = 0.0;
-    let mut longest_len_dy = 0.0;
+    /// Lines are flown both ways, so angles pi apart are the same heading
+    fn same_line_angle(a: f64, b: f64) -> bool {
+        let diff = (a - b).rem_euclid(std::f64::consts::PI);
+        diff < TOLERANCE || std::f64::consts::PI - diff < TOLERANCE
+    }
+
+    #[test]
+    fn lawnmower_angle_follows_long_side() {
+        for angle in [
+            0.0,
+            std::f64::consts::FRAC_PI_2,
+            30_f64.to_radians(),
+            -70_f64.to_radians(),
+        ] {
+            let coords = rectangle(400.0, 100.0, angle);
+            let refs: Vec<&Coord> = coords.iter().collect();
+            let lawnmower = get_lawnmower_angle(&refs, NZTM).unwrap();
+            assert!(
+                same_line_angle(lawnmower, angle),
+                "expected {} got {}",
+                angle,
+                lawnmower
+            );
+        }
+    }
+
+    /// Compares compass bearings, where 0 and 360 are both north
+    fn same_bearing(a: f64, b: f64) -> bool {
+        let diff = (a - b).rem_euclid(360.0);
+        diff < 1e-6 || 360.0 - diff < 1e-6
+    }
+
+    #[test]
+    fn math_angle_to_bearing() {
+        use std::f64::consts::{FRAC_PI_2, PI};
+
+        // East and north
+        assert!(same_bearing(math_angle_to_compass_bearing(0.0), 90.0));
+        assert!(same_bearing(math_angle_to_compass_bearing(FRAC_PI_2), 0.0));
+        // Always within 0..360, never negative
+        for (angle, bearing) in [
+            (PI, 270.0),
+            (-FRAC_PI_2, 180.0),
+            (100_f64.to_radians(), 350.0),
+        ] {
+            let converted = math_angle_to_compass_bearing(angle);
+            assert!((0.0..360.0).contains(&converted));
+            assert!(
+                same_bearing(converted, bearing),
+                "{} became {}",
+                bearing,
+                converted
+            );
+        }
+    }
+
+    #[test]
+    fn bearing_to_math_angle() {
+        assert!(compass_bearing_to_math_angle(90.0).abs() < TOLERANCE);
+        assert!(
+            (compass_bearing_to_math_angle(0.0) - std::f64::consts::FRAC_PI_2).abs() < TOLERANCE
+        );
+    }
+
+    #[test]
+    fn bearing_round_trip() {
+        for bearing in [0.0, 45.0, 90.0, 135.0, 200.0, 359.0, 360.0] {
+            let round_trip = math_angle_to_compass_bearing(compass_bearing_to_math_angle(bearing));
+            assert!(
+                same_bearing(round_trip, bearing),
+                "{} became {}",
+                bearing,
+                round_trip
+            );
+        }
+    }
+
+    #[test]
+    fn truncate_cuts_at_last_reachable_waypoint() {
+        let mut waypoints: Vec<Waypoint> = (0..6)
+            .map(|i| waypoint([172.6, -43.5 + i as f64 * 0.001]))
+            .collect();
+        let legs = get_leg_distances(&waypoints);
+        let total: f64 = legs.iter().sum();
+
+        // Halfway along the third leg, so the first three waypoints are kept
+        let max_distance = legs[0] + legs[1] + legs[2] / 2.0;
+        let (removed, share) = truncate_to_distance(&mut waypoints, max_distance).unwrap();
+        assert_eq!(removed, 3);
+        assert_eq!(waypoints.len(), 3);
+        assert_eq!(waypoints[2].position, [172.6, -43.5 + 2.0 * 0.001]);
+        assert!((share - (1.0 - (legs[0] + legs[1]) / total)).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn truncate_keeps_mission_within_distance() {
+        let mut waypoints: Vec<Waypoint> = (0..4)
+            .map(|i| waypoint([172.6, -43.5 + i as f64 * 0.001]))
+            .collect();
+        let total: f64 = get_leg_distances(&waypoints).iter().sum();
+        assert!(truncate_to_distance(&mut waypoints, total + 1.0).is_none());
+        assert_eq!(waypoints.len(), 4);
+    }
+
+    const A: [f64; 2] = [1_750_000.0, 5_900_000.0];
+    const B: [f64; 2] = [1_750_100.0, 5_900_000.0];
+    const C: [f64; 2] = [1_750_100.0, 5_900_100.0];
+
+    #[test]
+    fn duplicate_vertices_removed_consecutively() {
+        // An exact repeat and one a few millimetres off both go
+        let near_b = [B[0] + 0.005, B[1]];
+        let (points, removed) = remove_duplicate_vertices(&[A, B, B, near_b, C, A], NZTM).unwrap();
+        assert_eq!(removed, 2);
+        let expected: Vec<Coord> = [A, B, C, A].iter().map(|&c| c.into()).collect();
+        assert_eq!(points, expected);
+    }
+
+    #[test]
+    fn duplicate_closing_vertex_removed() {
+        let (points, removed) = remove_duplicate_vertices(&[A, B, C, A, A], NZTM).unwrap();
+        assert_eq!(removed, 1);
+        // Still closed, the first closing vertex is kept
+        assert_eq!(points.len(), 4);
+        assert_eq!(points.first(), points.last());
+    }
+
+    #[test]
+    fn duplicate_vertices_can_collapse_ring() {
+        let (points, removed) = remove_duplicate_vertices(&[A, A, B, B, A], NZTM).unwrap();
+        assert_eq!(removed, 2);
+        // Only two distinct points are left, which enclose no area
+        let expected: Vec<Coord> = [A, B, A].iter().map(|&c| c.into()).collect();
+        assert_eq!(points, expected);
+        let polygon = Polygon::new(LineString::from(points), vec![]);
+        assert_eq!(polygon.unsigned_area(), 0.0);
+    }
+
+    #[test]
+    fn fallback_lines_reach_both_edges() {
+        // 95 m across the lines, not a whole number of 10 m spacings
+        let corners = [
+            (0.0, 0.0),
+            (95.0, 0.0),
+            (95.0, 300.0),
+            (0.0, 300.0),
+            (0.0, 0.0),
+        ];
+        let polygon = Polygon::new(
+            corners
+                .iter()
+                .map(|&(x, y)| (A[0] + x, A[1] + y))
+                .collect::<Vec<_>>()
+                .into(),
+            vec![],
+        );
+        let angle = std::f64::consts::FRAC_PI_2;
+        let points = get_fallback_points(&polygon, &angle, &10.0, &20.0, None, NZTM).unwrap();
+
+        let min_x = points
+            .iter()
+            .map(|(p, _)| p.x - A[0])
+            .fold(f64::INFINITY, f64::min);
+        let max_x = points
+            .iter()
+            .map(|(p, _)| p.x - A[0])
+            .fold(f64::NEG_INFINITY, f64::max);
+        assert!(
+            min_x <= 10.0,
+            "west edge uncovered, first line at {}",
+            min_x
+        );
+        assert!(max_x >= 85.0, "east edge uncovered, last line at {}", max_x);
+    }
+
+    #[test]
+    fn fallback_points_at_photo_spacing_inside_area() {
+        let corners = [
+            (0.0, 0.0),
+            (95.0, 0.0),
+            (95.0, 310.0),
+            (0.0, 310.0),
+            (0.0, 0.0),
+        ];
+        let polygon = Polygon::new(
+            corners
+                .iter()
+                .map(|&(x, y)| (A[0] + x, A[1] + y))
+                .collect::<Vec<_>>()
+                .into(),
+            vec![],
+        );
+        let angle = std::f64::consts::FRAC_PI_2;
+        let points = get_fallback_points(&polygon, &angle, &10.0, &20.0, None, NZTM).unwrap();
+
+        // 10 lines of 310 m, each with a photo every 20 m from the edge
+        assert_eq!(points.len(), 10 * 16);
+        for (point, _) in &points {
+            assert_ne!(polygon.coordinate_position(point), CoordPos::Outside);
+        }
+        for pair in points.windows(2).filter(|pair| pair[0].1 == pair[1].1) {
+            let step = pair[1].0 - pair[0].0;
+            assert!((step.x.hypot(step.y) - 20.0).abs() < 1e-6);
+        }
+        // Alternate lines are flown in opposite directions
+        assert!(points[1].0.y > points[0].0.y);
+        assert!(points[17].0.y < points[16].0.y);
+    }
+
+    /// Checks the rectangle's bounds take in every vertex of the polygon
+    fn assert_encloses(rect: &Polygon, polygon: &Polygon) {
+        let outer = rect.bounding_rect().unwrap();
+        let inner = polygon.bounding_rect().unwrap();
+        assert!(outer.min().x <= inner.min().x + 1e-6 && outer.min().y <= inner.min().y + 1e-6);
+        assert!(outer.max().x >= inner.max().x - 1e-6 && outer.max().y >= inner.max().y - 1e-6);
+    }
 
-    for i in 0..mbr_coords.len() - 1 {
-        let (x1, y1) = to_nztm
-            .convert((mbr_coords[i].x, mbr_coords[i].y))
-            .expect("Cannot convert coords to nztm");
-        let (x2, y2) = to_nztm
-            .convert((mbr_coords[i + 1].x, mbr_coords[i + 1].y))
-            .expect("Cannot convert coords to nztm");
+    #[test]
+    fn mbr_of_sliver_polygon() {
+        // A kilometre long and a micrometre wide
+        let sliver = Polygon::new(
+            vec![
+                (A[0], A[1]),
+                (A[0] + 1000.0, A[1] + 1000.0),
+                (A[0] + 1000.0, A[1] + 1000.000001),
+            ]
+            .into(),
+            vec![],
+        );
+        assert_encloses(&get_mbr(&sliver), &sliver);
+    }
+
+    #[test]
+    fn mbr_of_collinear_polygon() {
+        // The hull of points in a line has no area at all
+        let collinear = Polygon::new(
+            vec![(A[0], A[1]), (A[0] + 500.0, A[1]), (A[0] + 1000.0, A[1])].into(),
+            vec![],
+        );
+        assert_encloses(&get_mbr(&collinear), &collinear);
+    }
 
-        let dx = x2 - x1;
-        let dy = y2 - y1;
+    #[test]
+    fn refuses_area_across_antimeridian() {
+        // Off Taveuni, Fiji, running from 179.9°E to 179.9°W
+        let coords = [
+            [179.9, -16.9],
+            [-179.9, -16.9],
+            [-179.9, -16.7],
+            [179.9, -16.7],
+            [179.9, -16.9],
+        ];
+        assert!(crosses_antimeridian(&coords));
+        let result = compute_plan(&coords, &drone(), &MissionConfig::default());
+        assert!(matches!(result, Err(FlightPathError::CrossesAntimeridian)));
+    }
 
-        let dist = (dx * dx + dy * dy).sqrt();
+    #[test]
+    fn fiji_area_west_of_antimeridian_is_not_refused() {
+        let coords = [
+            [178.4, -18.2],
+            [178.5, -18.2],
+            [178.5, -18.1],
+            [178.4, -18.1],
+            [178.4, -18.2],
+        ];
+        assert!(!crosses_antimeridian(&coords));
+    }
 
-        if dist > max_dist {
-            max_dist = dist;
-            longest_len_dx = dx;
-            longest_len_dy = dy;
+    #[test]
+    fn cross_slope_narrows_lines_to_keep_sidelap() {
+        // Lines run north, across a plane rising 30 degrees to the east
+        let slope = 30_f64.to_radians();
+        let plane = SyntheticElevationSource::plane((slope.tan(), 0.0));
+        let corners = [
+            (0.0, 0.0),
+            (200.0, 0.0),
+            (200.0, 400.0),
+            (0.0, 400.0),
+            (0.0, 0.0),
+        ];
+        let polygon = Polygon::new(
+            corners
+                .iter()
+                .map(|&(x, y)| (A[0] + x, A[1] + y))
+                .collect::<Vec<_>>()
+                .into(),
+            vec![],
+        );
+        let config = MissionConfig {
+            input_crs: NZTM.to_string(),
+            ..Default::default()
+        };
+        let spacing = 20.0;
+        let waypoints = get_waypoints_with_slope_adjustment(
+            &polygon,
+            &std::f64::consts::FRAC_PI_2,
+            &spacing,
+            Some(&plane as &dyn ElevationSource),
+            &drone(),
+            &config,
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        // Every photo on a line is moved the same way on a plane, so the lines stay
+        // straight and their eastings can be compared directly
+        let to_nztm = get_projection(WGS84, NZTM).unwrap();
+        let line_count = waypoints.iter().map(|w| w.line).max().unwrap() + 1;
+        let eastings: Vec<f64> = (0..line_count)
+            .map(|line| {
+                let [lon, lat] = waypoints.iter().find(|w| w.line == line).unwrap().position;
+                to_nztm.convert((lon, lat)).unwrap().0
+            })
+            .collect();
+        assert!(line_count > 5);
+        for pair in eastings.windows(2) {
+            // The gap measured along the slope is the flat-ground line spacing
+            let along_slope = (pair[1] - pair[0]).abs() / slope.cos();
+            assert!(
+                (along_slope - spacing).abs() < 1e-3,
+                "lines {} m apart on the slope",
+                along_slope
+            );
         }
     }
 
-    longest_len_dy.atan2(longest_len_dx)
+    #[test]
+    fn area_missed_by_every_line_is_an_error() {
+        // A 2 m square, far smaller than one footprint, with the grid anchored so the
+        // lines either side of it pass about 20 m and 30 m clear
+        let coords = [
+            A,
+            [A[0] + 2.0, A[1]],
+            [A[0] + 2.0, A[1] + 2.0],
+            [A[0], A[1] + 2.0],
+            A,
+        ];
+        let config = MissionConfig {
+            input_crs: NZTM.to_string(),
+            anchor: Some([A[0] + 21.0, A[1] + 21.0]),
+            ..Default::default()
+        };
+        let result = compute_plan(&coords, &drone(), &config);
+        assert!(matches!(result, Err(FlightPathError::NoWaypoints)));
+    }
+
+    #[test]
+    fn corridor_slope_is_cross_slope_of_plane() {
+        let line = [
+            Coord { x: 0.0, y: 0.0 },
+            Coord { x: 0.0, y: 100.0 },
+            Coord { x: 0.0, y: 200.0 },
+        ];
+        let across = Coord { x: 20.0, y: 0.0 };
+
+        // Rising 0.5 m per meter across the corridor
+        let cross = SyntheticElevationSource::plane((0.5, 0.0));
+        let slope = calculate_corridor_slope(&line, &cross, across);
+        assert!((slope - 0.5_f64.atan()).abs() < TOLERANCE);
+        // Falling away the other side is just as steep
+        let slope = calculate_corridor_slope(&line, &cross, -across);
+        assert!((slope - 0.5_f64.atan()).abs() < TOLERANCE);
+
+        // A slope along the line doesn't change the ground covered across it
+        let along = SyntheticElevationSource::plane((0.0, 0.5));
+        assert!(calculate_corridor_slope(&line, &along, across).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn corridor_slope_narrows_line_spacing() {
+        let line = [Coord { x: 0.0, y: 0.0 }, Coord { x: 0.0, y: 200.0 }];
+        let across = Coord { x: 20.0, y: 0.0 };
+        let gentle = SyntheticElevationSource::plane((0.2, 0.0));
+        let steep = SyntheticElevationSource::plane((1.0, 0.0));
+
+        // The next line is the base spacing times the cosine of the corridor slope
+        let gentle_spacing = 20.0 * calculate_corridor_slope(&line, &gentle, across).cos();
+        let steep_spacing = 20.0 * calculate_corridor_slope(&line, &steep, across).cos();
+        assert!(steep_spacing < gentle_spacing && gentle_spacing < 20.0);
+        assert!((steep_spacing - 20.0 / 2_f64.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn corridor_slope_without_data_is_flat() {
+        let line = [Coord { x: 0.0, y: 0.0 }, Coord { x: 0.0, y: 200.0 }];
+        let mut plane = SyntheticElevationSource::plane((0.5, 0.0));
+        plane.extent = 10.0;
+        // The far side of the corridor is off the data everywhere
+        assert_eq!(
+            calculate_corridor_slope(&line, &plane, Coord { x: 20.0, y: 0.0 }),
+            0.0
+        );
+        assert_eq!(
+            calculate_corridor_slope(&[], &plane, Coord { x: 20.0, y: 0.0 }),
+            0.0
+        );
+    }
+
+    #[test]
+    fn axis_gradient_falls_back_to_one_side() {
+        assert_eq!(
+            axis_gradient(Some(14.0), Some(12.0), Some(10.0), 2.0),
+            Some(1.0)
+        );
+        assert_eq!(axis_gradient(None, Some(12.0), Some(10.0), 2.0), Some(1.0));
+        assert_eq!(axis_gradient(Some(14.0), Some(12.0), None, 2.0), Some(1.0));
+        assert_eq!(axis_gradient(Some(14.0), None, None, 2.0), None);
+        assert_eq!(axis_gradient(None, None, None, 2.0), None);
+    }
+
+    #[test]
+    fn slope_one_pixel_from_raster_edge() {
+        let mut plane = SyntheticElevationSource::plane((0.3, 0.4));
+        plane.extent = 100.0;
+        // The samples two pixels east and north are off the raster
+        let point = Coord { x: 99.0, y: 99.0 };
+        assert!(plane.elevation_at(point.x + 2.0, point.y).is_none());
+        let slope = calculate_slope_at_point(point, &plane);
+        assert!((slope - 0.5_f64.atan()).abs() < TOLERANCE);
+
+        // And the same at the west and south edges
+        let point = Coord { x: -99.0, y: -99.0 };
+        let slope = calculate_slope_at_point(point, &plane);
+        assert!((slope - 0.5_f64.atan()).abs() < TOLERANCE);
+    }
+
+    /// Half the width in degrees of the footprint of a waypoint over `terrain`
+    fn footprint_half_width(terrain: Option<f64>, config: &MissionConfig) -> f64 {
+        let centre = [172.6, -43.5];
+        let mut photo = waypoint(centre);
+        photo.coverage_rect.coords = [
+            [172.599, -43.499],
+            [172.599, -43.501],
+            [172.601, -43.501],
+            [172.601, -43.499],
+            [172.599, -43.499],
+        ];
+        photo.altitude = 200.0;
+        photo.terrain_elevation = terrain;
+        photo.gsd = Some(2.0);
+        let mut photos = [photo];
+        scale_footprints_to_agl(&mut photos, &[100.0], config);
+        let half_width = photos[0].coverage_rect.coords[2][0] - centre[0];
+        assert!((photos[0].gsd.unwrap() / 2.0 - half_width / 0.001).abs() < TOLERANCE);
+        half_width
+    }
+
+    #[test]
+    fn footprint_tracks_height_above_terrain_step() {
+        // Flying at 200 m above sea level, 100 m above the ground nominally
+        let config = MissionConfig {
+            height_mode: HeightMode::Wgs84,
+            geoid_offset_m: Some(0.0),
+            ..Default::default()
+        };
+        // On the valley floor the camera is at the nominal height
+        assert!((footprint_half_width(Some(100.0), &config) - 0.001).abs() < TOLERANCE);
+        // Stepping up onto a 150 m terrace halves the height and the footprint
+        assert!((footprint_half_width(Some(150.0), &config) - 0.0005).abs() < TOLERANCE);
+        // And down to 50 m makes it half again as big
+        assert!((footprint_half_width(Some(50.0), &config) - 0.0015).abs() < TOLERANCE);
+        // Without terrain data the nominal footprint is kept
+        assert!((footprint_half_width(None, &config) - 0.001).abs() < TOLERANCE);
+    }
+
+    /// Closed ring of the NZTM rectangle spanning `min` to `max` meters from A
+    fn ring(min: (f64, f64), max: (f64, f64)) -> Vec<[f64; 2]> {
+        [
+            (min.0, min.1),
+            (max.0, min.1),
+            (max.0, max.1),
+            (min.0, max.1),
+            (min.0, min.1),
+        ]
+        .iter()
+        .map(|&(x, y)| [A[0] + x, A[1] + y])
+        .collect()
+    }
+
+    #[test]
+    fn keep_in_clips_grid() {
+        let coords = ring((0.0, 0.0), (600.0, 600.0));
+        let config = MissionConfig {
+            input_crs: NZTM.to_string(),
+            ..Default::default()
+        };
+        let full = compute_plan(&coords, &drone(), &config).unwrap();
+
+        // Only the western half of the area is authorised
+        let config = MissionConfig {
+            keep_in: Some(ring((-100.0, -100.0), (250.0, 700.0))),
+            ..config
+        };
+        let clipped = compute_plan(&coords, &drone(), &config).unwrap();
+        assert!(!clipped.waypoints.is_empty());
+        assert!(clipped.waypoints.len() < full.waypoints.len());
+
+        let to_nztm = get_projection(WGS84, NZTM).unwrap();
+        for waypoint in &clipped.waypoints {
+            let [lon, lat] = waypoint.position;
+            let (x, _) = to_nztm.convert((lon, lat)).unwrap();
+            assert!(x - A[0] <= 250.0 + 1e-3, "waypoint {} m east", x - A[0]);
+        }
+    }
 }
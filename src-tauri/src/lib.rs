@@ -1,4 +1,13 @@
+mod area;
+mod config;
+mod contour;
+mod coverage;
+mod elevation;
+mod export;
 mod flight_path;
+mod perimeter;
+mod profile;
+mod sun;
 mod writer;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -6,7 +15,25 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![flight_path::generate_flightpath])
+        .invoke_handler(tauri::generate_handler![
+            flight_path::generate_flightpath,
+            flight_path::estimate_mission,
+            flight_path::footprint_at,
+            flight_path::coverage_footprints,
+            flight_path::mission_summary,
+            flight_path::minimum_rect,
+            flight_path::merge_plans,
+            flight_path::retime_plan,
+            coverage::overlap_report,
+            coverage::coverage_heatmap,
+            area::validate_area,
+            area::load_area_from_shapefile,
+            area::area_metrics,
+            export::export_plan,
+            export::plan_as_geojson,
+            export::plan_as_enu,
+            profile::profile_schema
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,88 @@
+use crate::config::MissionConfig;
+use crate::contour::resample;
+use crate::elevation::ElevationData;
+use crate::flight_path::{
+    calculate_gsd, generate_coverage_rect, get_coord_meters, get_projection,
+    math_angle_to_compass_bearing, Drone, FlightPathError, Waypoint, NZTM, WGS84,
+};
+use gdal::Dataset;
+use geo::{Coord, LineString, Polygon, Winding};
+
+/// Returns waypoints every `spacing` meters around the edge of the search area, as a
+/// single line. The aircraft faces into the area if `face_inward`, otherwise along
+/// the edge, with the gimbal at `gimbal_pitch`. Heights follow the terrain where
+/// there is elevation data.
+pub fn get_perimeter_waypoints(
+    polygon: &Polygon,
+    spacing: f64,
+    face_inward: bool,
+    gimbal_pitch: f64,
+    vrt_path: &str,
+    drone: &Drone,
+    config: &MissionConfig,
+) -> Result<Vec<Waypoint>, FlightPathError> {
+    let input_crs = config.input_crs.as_str();
+    let ring_meters = LineString::from(get_coord_meters(
+        &polygon.exterior().coords().collect::<Vec<_>>(),
+        input_crs,
+    )?);
+    let points = resample(&ring_meters.0, spacing);
+
+    // The inside of an anticlockwise ring is on the left of the direction of travel
+    let inward_turn = if ring_meters.is_ccw() {
+        std::f64::consts::FRAC_PI_2
+    } else {
+        -std::f64::consts::FRAC_PI_2
+    };
+
+    let dataset = Dataset::open(vrt_path).ok();
+    let elevation = dataset.as_ref().and_then(|dataset| {
+        ElevationData::new(dataset, config.elevation_band, config.vertical_units).ok()
+    });
+
+    let to_wgs84 = get_projection(NZTM, WGS84)?;
+    let mut waypoints = Vec::new();
+    for (k, point) in points.iter().enumerate() {
+        let direction = get_direction(&points, k);
+        let facing = if face_inward {
+            direction + inward_turn
+        } else {
+            direction
+        };
+        let perp_angle = direction + std::f64::consts::FRAC_PI_2;
+        let coverage_rect = generate_coverage_rect(point, &0.0, &perp_angle, drone, &to_wgs84)?;
+
+        if let Ok((lon, lat)) = to_wgs84.convert((point.x, point.y)) {
+            waypoints.push(Waypoint {
+                coverage_rect,
+                position: [lon, lat],
+                bearing: 0.0,
+                altitude: drone.altitude,
+                line: 0,
+                zoom: None,
+                gsd: calculate_gsd(drone, drone.altitude),
+                terrain_elevation: elevation
+                    .as_ref()
+                    .and_then(|elevation| elevation.elevation_at(point.x, point.y)),
+                capture: true,
+                layer: 0,
+                gimbal_pitch,
+                speed: None,
+                heading: Some(math_angle_to_compass_bearing(facing)),
+                timelapse: None,
+            });
+        }
+    }
+    Ok(waypoints)
+}
+
+/// Returns the planar angle of travel from the point at `k` to the next, or from the
+/// one before for the last point
+fn get_direction(points: &[Coord], k: usize) -> f64 {
+    let (from, to) = match points.get(k + 1) {
+        Some(next) => (points[k], *next),
+        None if k > 0 => (points[k - 1], points[k]),
+        None => return 0.0,
+    };
+    (to.y - from.y).atan2(to.x - from.x)
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+
+/// Fastest speed in m/s the UI allows for aircraft without a known limit
+const DEFAULT_MAX_SPEED: f64 = 30.0;
+
+/// One input of a drone profile, so the UI can build the form for it
+#[derive(Serialize, Deserialize)]
+pub struct ProfileField {
+    /// Name of the field on the drone profile
+    pub name: String,
+    /// Units the value is entered in, None for text
+    pub unit: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub description: String,
+}
+
+/// The drone profile inputs that apply to a model
+#[derive(Serialize, Deserialize)]
+pub struct ProfileSchema {
+    pub required: Vec<ProfileField>,
+    pub optional: Vec<ProfileField>,
+}
+
+/// Returns the waypoint speed limit in m/s and service ceiling in meters of the DJI
+/// models whose limits DJI Pilot enforces, or None for other models
+pub(crate) fn known_model_limits(model: &str) -> Option<(f64, f64)> {
+    let model = model.to_uppercase();
+    if ["M30", "M300", "M350", "MATRICE 30"]
+        .iter()
+        .any(|name| model.contains(name))
+    {
+        Some((15.0, 7000.0))
+    } else if ["M3E", "M3T", "MAVIC 3"]
+        .iter()
+        .any(|name| model.contains(name))
+    {
+        Some((15.0, 6000.0))
+    } else if ["P4", "PHANTOM 4"].iter().any(|name| model.contains(name)) {
+        Some((14.0, 6000.0))
+    } else {
+        None
+    }
+}
+
+/// Returns the drone profile fields the UI should show for the model, with their
+/// units and valid ranges. Limits that are already known for the model aren't asked for.
+#[tauri::command]
+pub fn profile_schema(model: String) -> ProfileSchema {
+    let known_limits = known_model_limits(&model);
+    let max_speed = known_limits.map_or(DEFAULT_MAX_SPEED, |(speed, _)| speed);
+
+    let required = vec![
+        field("model", None, None, None, "Aircraft model name"),
+        field(
+            "fov",
+            Some("°"),
+            Some(10.0),
+            Some(180.0),
+            "Camera field of view",
+        ),
+        field(
+            "altitude",
+            Some("m"),
+            Some(1.0),
+            None,
+            "Flying height above the ground",
+        ),
+        field(
+            "overlap",
+            Some("%"),
+            Some(0.0),
+            Some(99.0),
+            "Overlap between neighbouring photos",
+        ),
+        field(
+            "speed",
+            Some("m/s"),
+            Some(0.5),
+            Some(max_speed),
+            "Cruise speed along the lines",
+        ),
+    ];
+
+    let mut optional = vec![
+        field(
+            "focal_length",
+            Some("mm"),
+            Some(1.0),
+            None,
+            "Camera focal length, needed for the GSD",
+        ),
+        field(
+            "pixel_pitch",
+            Some("µm"),
+            Some(0.5),
+            Some(20.0),
+            "Sensor pixel pitch, needed for the GSD",
+        ),
+        field(
+            "endurance",
+            Some("min"),
+            Some(1.0),
+            Some(120.0),
+            "Usable flight time of one battery",
+        ),
+        field(
+            "min_shutter_interval_s",
+            Some("s"),
+            Some(0.1),
+            Some(10.0),
+            "Shortest time the camera needs between photos",
+        ),
+    ];
+    if known_limits.is_none() {
+        optional.push(field(
+            "max_auto_speed",
+            Some("m/s"),
+            Some(0.5),
+            Some(DEFAULT_MAX_SPEED),
+            "Fastest speed the aircraft flies a waypoint mission at",
+        ));
+        optional.push(field(
+            "max_altitude",
+            Some("m"),
+            Some(1.0),
+            None,
+            "Highest altitude above sea level the aircraft can fly at",
+        ));
+    }
+
+    ProfileSchema { required, optional }
+}
+
+fn field(
+    name: &str,
+    unit: Option<&str>,
+    min: Option<f64>,
+    max: Option<f64>,
+    description: &str,
+) -> ProfileField {
+    ProfileField {
+        name: name.to_string(),
+        unit: unit.map(str::to_string),
+        min,
+        max,
+        description: description.to_string(),
+    }
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::flight_path::compass_bearing_to_math_angle;
+
+/// Advice on orienting the flight lines relative to the sun
+#[derive(Serialize, Deserialize)]
+pub struct SunHint {
+    /// Compass bearing of the sun in degrees
+    pub azimuth: f64,
+    /// Height of the sun above the horizon in degrees
+    pub elevation: f64,
+    /// Lawnmower angle (same convention as `heading_angle`) that flies perpendicular
+    /// to the sun, which minimises glare and hotspots in the photos
+    pub suggested_heading_angle: f64,
+}
+
+/// Returns the sun position at the given WGS84 location and time, along with the
+/// flight line angle that keeps the sun off the nose and tail of the aircraft
+pub fn get_sun_hint(lon: f64, lat: f64, time: DateTime<Utc>) -> SunHint {
+    let (azimuth, elevation) = get_solar_position(lon, lat, time);
+
+    // Fly across the sun's bearing, in the planar math angle used for the flight lines
+    let suggested_heading_angle = compass_bearing_to_math_angle(azimuth + 90.0);
+
+    SunHint {
+        azimuth,
+        elevation,
+        suggested_heading_angle,
+    }
+}
+
+/// Returns the solar azimuth (compass degrees) and elevation (degrees) using the
+/// low precision almanac formulas, accurate to about a degree
+fn get_solar_position(lon: f64, lat: f64, time: DateTime<Utc>) -> (f64, f64) {
+    // Days since the J2000 epoch
+    let d = time.timestamp() as f64 / 86400.0 + 2440587.5 - 2451545.0;
+
+    let mean_anomaly = (357.529 + 0.98560028 * d).to_radians();
+    let mean_longitude = 280.459 + 0.98564736 * d;
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.00000036 * d).to_radians();
+
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    // Local sidereal time gives the hour angle of the sun
+    let sidereal_time = (18.697374558 + 24.06570982441908 * d) * 15.0 + lon;
+    let hour_angle = sidereal_time.to_radians() - right_ascension;
+
+    let lat = lat.to_radians();
+    let elevation =
+        (lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos()).asin();
+    let azimuth =
+        (-hour_angle.sin()).atan2(lat.cos() * declination.tan() - lat.sin() * hour_angle.cos());
+
+    (
+        azimuth.to_degrees().rem_euclid(360.0),
+        elevation.to_degrees(),
+    )
+}
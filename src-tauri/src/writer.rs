@@ -1,48 +1,118 @@
 use quick_xml::{
     events::{BytesEnd, BytesStart, BytesText, Event},
+    reader::Reader,
     writer::Writer,
 };
-use zip::{write::FileOptions, write::ZipWriter, CompressionMethod::Stored};
+use zip::{write::FileOptions, write::ZipWriter, CompressionMethod};
 
-use crate::flight_path::{Drone, Waypoint};
-use std::{fs, io::Cursor, io::Write};
+use crate::config::{ActionTrigger, CaptureMode, HeightMode, MissionConfig};
+use crate::export::{write_flight_line_style, write_line_placemark, write_waypoints_folder};
+use crate::flight_path::{
+    get_leg_distances, math_angle_to_compass_bearing, Drone, TimelapseMark, Waypoint,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, io::Cursor, io::Write, ops::Range};
+
+/// 35mm-equivalent focal length of the camera at 1x zoom. DJI's `zoom` action
+/// takes a focal length rather than a ratio, so ratios are scaled by this.
+/// Only zoom-capable payloads honour the action: the M30/M30T, the H20/H20T/H30
+/// on the M300/M350 and the Mavic 3 Enterprise/Thermal. Other aircraft ignore it.
+const ZOOM_BASE_FOCAL_LENGTH: f64 = 24.0;
+
+/// Number of decimal places used for the numeric values written into the WPML
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct OutputPrecision {
+    pub coordinates: usize,
+    pub altitude: usize,
+}
+
+impl Default for OutputPrecision {
+    fn default() -> Self {
+        // 7 decimal places of lat/lon is ~1cm, well below GPS accuracy
+        OutputPrecision {
+            coordinates: 7,
+            altitude: 2,
+        }
+    }
+}
+
+/// Compression applied to the files inside the KMZ archive
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub enum Compression {
+    Stored,
+    #[default]
+    Deflated,
+}
+
+impl From<Compression> for CompressionMethod {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Stored => CompressionMethod::Stored,
+            Compression::Deflated => CompressionMethod::Deflated,
+        }
+    }
+}
 
-pub async fn write_wqml(waypoints: &[Waypoint], heading_angle: &f64, drone: &Drone) {
-    match create_kmz(waypoints, heading_angle, drone).await {
-        Ok(_) => println!("WPMZ file created successfully"),
+/// Writes the mission KMZ, returning its path and the number of waypoints in it
+pub async fn write_wqml(
+    waypoints: &[Waypoint],
+    heading_angle: &f64,
+    drone: &Drone,
+    config: &MissionConfig,
+) -> Option<(String, usize)> {
+    match create_kmz(
+        waypoints,
+        heading_angle,
+        drone,
+        config,
+        "../output/wpmz.kmz",
+        0,
+    )
+    .await
+    {
+        Ok(written) => {
+            println!("WPMZ file created successfully");
+            Some(written)
+        }
         Err(e) => {
             println!("Error creating WPMZ: {}", e);
+            None
         }
-    };
+    }
 }
 
 pub async fn create_kmz(
     waypoints: &[Waypoint],
     heading_angle: &f64,
     drone: &Drone,
-) -> Result<(), Box<dyn std::error::Error>> {
+    config: &MissionConfig,
+    zip_path: &str,
+    photo_offset: usize,
+) -> Result<(String, usize), Box<dyn std::error::Error>> {
     let dir_path = "../tmp/wpmz";
     fs::create_dir_all(dir_path)?;
 
     // Ensure output directory exists
-    fs::create_dir_all("../output")?;
+    if let Some(parent) = std::path::Path::new(zip_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
 
     let flightplan_path = format!("{}/flightplan.wpml", dir_path);
     let template_path = format!("{}/template.kml", dir_path);
 
     // Generate and write the WPML content
-    let wpml_content = generate_wpml(waypoints, heading_angle, drone)?;
+    let (wpml_content, placemarks) =
+        generate_wpml(waypoints, heading_angle, drone, config, photo_offset)?;
     fs::write(&flightplan_path, &wpml_content)?;
 
-    // Create a basic template.kml (you might want to customize this)
-    let template_content = create_template_kml()?;
+    // The template holds a viewable copy of the route alongside the waylines
+    let template_content = create_template_kml(waypoints)?;
     fs::write(&template_path, template_content)?;
 
     // Create the zip file
-    let zip_path = "../output/wpmz.kmz";
     let zip_file = fs::File::create(zip_path)?;
     let mut zip = ZipWriter::new(zip_file);
-    let zip_options = FileOptions::<()>::default().compression_method(Stored);
+    let zip_options = FileOptions::<()>::default().compression_method(config.compression.into());
 
     // Add flightplan.wpml to zip
     zip.start_file("flightplan.wpml", zip_options)?;
@@ -60,10 +130,107 @@ pub async fn create_kmz(
     fs::remove_dir_all(dir_path)?;
 
     println!("Created zip file at: {}", zip_path);
-    Ok(())
+    Ok((absolute_path(zip_path), placemarks))
+}
+
+/// One battery's part of a split mission, as listed in the manifest
+#[derive(Serialize)]
+struct MissionSegment {
+    file: String,
+    /// Index of the first waypoint in the whole mission
+    first_waypoint: usize,
+    /// Index of the last waypoint in the whole mission
+    last_waypoint: usize,
+    /// Index in the whole mission of the waypoint this mission resumes from, which is
+    /// flown to first without taking a photo
+    resume_waypoint: Option<usize>,
+    /// Estimated flight time in minutes
+    est_flight_time: f64,
 }
 
-fn create_template_kml() -> Result<String, Box<dyn std::error::Error>> {
+/// Writes a separate KMZ for each battery's range of waypoints, plus a JSON manifest
+/// listing them. Returns the manifest path and the KMZ paths.
+///
+/// With `resume_segments` each mission after the first starts with a non-capturing
+/// copy of the previous battery's last waypoint. DJI Pilot flies to the first
+/// waypoint as set by `wpml:flyToWaylineMode`, then follows the line from there, so
+/// the resumed mission picks up where the last one stopped. Photo numbers from
+/// `wpml:fileSuffix` carry on from that waypoint's index in the whole mission.
+pub async fn write_battery_kmzs(
+    waypoints: &[Waypoint],
+    segments: &[(Range<usize>, f64)],
+    heading_angle: &f64,
+    drone: &Drone,
+    config: &MissionConfig,
+) -> Option<(String, Vec<String>)> {
+    let mut manifest = Vec::new();
+    for (i, (range, est_flight_time)) in segments.iter().enumerate() {
+        let zip_path = format!("../output/mission_{}.kmz", i + 1);
+        let resume_waypoint = range
+            .start
+            .checked_sub(1)
+            .filter(|_| config.resume_segments);
+        let segment = match resume_waypoint {
+            Some(resume) => {
+                let mut segment = vec![Waypoint {
+                    capture: false,
+                    zoom: None,
+                    ..waypoints[resume]
+                }];
+                segment.extend_from_slice(&waypoints[range.clone()]);
+                segment
+            }
+            None => waypoints[range.clone()].to_vec(),
+        };
+        match create_kmz(
+            &segment,
+            heading_angle,
+            drone,
+            config,
+            &zip_path,
+            resume_waypoint.unwrap_or(range.start),
+        )
+        .await
+        {
+            Ok((file, _)) => manifest.push(MissionSegment {
+                file,
+                first_waypoint: range.start,
+                last_waypoint: range.end - 1,
+                resume_waypoint,
+                est_flight_time: *est_flight_time,
+            }),
+            Err(e) => {
+                println!("Error creating WPMZ for battery {}: {}", i + 1, e);
+                return None;
+            }
+        }
+    }
+
+    let manifest_path = "../output/missions.json";
+    let written = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(manifest_path, json).map_err(|e| e.to_string()));
+    match written {
+        Ok(_) => {
+            println!("Created mission manifest at: {}", manifest_path);
+            let files = manifest.into_iter().map(|segment| segment.file).collect();
+            Some((absolute_path(manifest_path), files))
+        }
+        Err(e) => {
+            println!("Error writing mission manifest: {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the absolute form of a path that has been written, for reporting to the UI
+pub fn absolute_path(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+fn create_template_kml(waypoints: &[Waypoint]) -> Result<String, Box<dyn std::error::Error>> {
     let mut writer = Writer::new(Cursor::new(Vec::new()));
 
     // XML declaration
@@ -86,6 +253,11 @@ fn create_template_kml() -> Result<String, Box<dyn std::error::Error>> {
     writer.write_event(Event::Text(BytesText::new("Template")))?;
     writer.write_event(Event::End(BytesEnd::new("name")))?;
 
+    // Waypoint placemarks and the route joining them
+    write_flight_line_style(&mut writer)?;
+    write_waypoints_folder(&mut writer, waypoints, false)?;
+    write_line_placemark(&mut writer, "Route", waypoints, None)?;
+
     // Close document and kml
     writer.write_event(Event::End(BytesEnd::new("Document")))?;
     writer.write_event(Event::End(BytesEnd::new("kml")))?;
@@ -95,11 +267,226 @@ fn create_template_kml() -> Result<String, Box<dyn std::error::Error>> {
     Ok(String::from_utf8(result)?)
 }
 
+/// Formats the photo file suffix for a waypoint from a pattern such as `SITE_A_{:04}`.
+/// The `{}` placeholder is replaced by the index, zero padded to the width given
+/// as `{:0N}`. Patterns without a placeholder have the index appended.
+fn format_file_suffix(pattern: &str, index: usize) -> String {
+    let placeholder = pattern
+        .find('{')
+        .and_then(|start| pattern[start..].find('}').map(|len| (start, start + len)));
+
+    match placeholder {
+        Some((start, end)) => {
+            let width = pattern[start + 1..end]
+                .strip_prefix(":0")
+                .and_then(|w| w.parse::<usize>().ok())
+                .unwrap_or(0);
+            format!(
+                "{}{:0width$}{}",
+                &pattern[..start],
+                index,
+                &pattern[end + 1..],
+                width = width
+            )
+        }
+        None => format!("{}{}", pattern, index),
+    }
+}
+
+/// Writes the actionTrigger of an action group, with its parameter if it takes one
+fn write_action_trigger(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    trigger: &ActionTrigger,
+) -> Result<(), Box<dyn std::error::Error>> {
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionTrigger")))?;
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionTriggerType")))?;
+    writer.write_event(Event::Text(BytesText::new(trigger.as_wpml())))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionTriggerType")))?;
+    if let Some(param) = trigger.param() {
+        writer.write_event(Event::Start(BytesStart::new("wpml:actionTriggerParam")))?;
+        writer.write_event(Event::Text(BytesText::new(&param.to_string())))?;
+        writer.write_event(Event::End(BytesEnd::new("wpml:actionTriggerParam")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionTrigger")))?;
+    Ok(())
+}
+
+/// Formats a compass bearing in degrees as a waypointHeadingAngle, which DJI takes in
+/// -180 to 180
+fn get_dji_heading(bearing: f64) -> String {
+    ((bearing + 180.0).rem_euclid(360.0) - 180.0).to_string()
+}
+
+/// Returns the gimbal pitch to write at each waypoint. With a ramp the pitch moves
+/// evenly from the previous pass's pitch to the new one over the first `ramp`
+/// waypoints of each pass, instead of snapping at its first waypoint.
+fn get_gimbal_pitches(waypoints: &[Waypoint], ramp: Option<usize>) -> Vec<f64> {
+    let mut pitches: Vec<f64> = waypoints.iter().map(|w| w.gimbal_pitch).collect();
+    let Some(ramp) = ramp.filter(|ramp| *ramp > 1) else {
+        return pitches;
+    };
+
+    let mut pass_start = 0;
+    for i in 1..waypoints.len() {
+        if waypoints[i].line != waypoints[i - 1].line {
+            pass_start = i;
+        }
+        let step = i - pass_start;
+        if pass_start == 0 || step >= ramp {
+            continue;
+        }
+        let from = waypoints[pass_start - 1].gimbal_pitch;
+        let to = waypoints[i].gimbal_pitch;
+        pitches[i] = from + (to - from) * (step + 1) as f64 / ramp as f64;
+    }
+    pitches
+}
+
+/// Writes a gimbalRotate action turning the gimbal to the given absolute pitch
+fn write_gimbal_rotate_action(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    action_id: usize,
+    pitch: f64,
+    payload_position: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    writer.write_event(Event::Start(BytesStart::new("wpml:action")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionId")))?;
+    writer.write_event(Event::Text(BytesText::new(&action_id.to_string())))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionId")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionActuatorFunc")))?;
+    writer.write_event(Event::Text(BytesText::new("gimbalRotate")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFunc")))?;
+
+    writer.write_event(Event::Start(BytesStart::new(
+        "wpml:actionActuatorFuncParam",
+    )))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRotateMode")))?;
+    writer.write_event(Event::Text(BytesText::new("absoluteAngle")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRotateMode")))?;
+
+    // Pitch control
+    writer.write_event(Event::Start(BytesStart::new(
+        "wpml:gimbalPitchRotateEnable",
+    )))?;
+    writer.write_event(Event::Text(BytesText::new("1")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalPitchRotateEnable")))?;
+    writer.write_event(Event::Start(BytesStart::new("wpml:gimbalPitchRotateAngle")))?;
+    writer.write_event(Event::Text(BytesText::new(&pitch.to_string())))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalPitchRotateAngle")))?;
+
+    // Roll control
+    writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRollRotateEnable")))?;
+    writer.write_event(Event::Text(BytesText::new("0")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRollRotateEnable")))?;
+    writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRollRotateAngle")))?;
+    writer.write_event(Event::Text(BytesText::new("0")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRollRotateAngle")))?;
+
+    // Yaw control
+    writer.write_event(Event::Start(BytesStart::new("wpml:gimbalYawRotateEnable")))?;
+    writer.write_event(Event::Text(BytesText::new("0")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalYawRotateEnable")))?;
+    writer.write_event(Event::Start(BytesStart::new("wpml:gimbalYawRotateAngle")))?;
+    writer.write_event(Event::Text(BytesText::new("0")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalYawRotateAngle")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRotateTimeEnable")))?;
+    writer.write_event(Event::Text(BytesText::new("0")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRotateTimeEnable")))?;
+    writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRotateTime")))?;
+    writer.write_event(Event::Text(BytesText::new("0")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRotateTime")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:payloadPositionIndex")))?;
+    writer.write_event(Event::Text(BytesText::new(payload_position)))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:payloadPositionIndex")))?;
+
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFuncParam")))?;
+
+    writer.write_event(Event::End(BytesEnd::new("wpml:action")))?;
+    Ok(())
+}
+
+/// Writes a takePhoto action saving the photo with the given file suffix
+fn write_take_photo_action(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    action_id: usize,
+    file_suffix: &str,
+    payload_position: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    writer.write_event(Event::Start(BytesStart::new("wpml:action")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionId")))?;
+    writer.write_event(Event::Text(BytesText::new(&action_id.to_string())))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionId")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionActuatorFunc")))?;
+    writer.write_event(Event::Text(BytesText::new("takePhoto")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFunc")))?;
+
+    writer.write_event(Event::Start(BytesStart::new(
+        "wpml:actionActuatorFuncParam",
+    )))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:fileSuffix")))?;
+    writer.write_event(Event::Text(BytesText::new(file_suffix)))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:fileSuffix")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:payloadPositionIndex")))?;
+    writer.write_event(Event::Text(BytesText::new(payload_position)))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:payloadPositionIndex")))?;
+
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFuncParam")))?;
+
+    writer.write_event(Event::End(BytesEnd::new("wpml:action")))?;
+    Ok(())
+}
+
+/// Writes an action group taking a photo each time the repeating `trigger` fires
+/// while the aircraft flies between the waypoints at the ends of `span`
+fn write_interval_photo_group(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    span: Range<usize>,
+    trigger: &ActionTrigger,
+    file_suffix: &str,
+    payload_position: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionGroup")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionGroupStartIndex")))?;
+    writer.write_event(Event::Text(BytesText::new(&span.start.to_string())))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionGroupStartIndex")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionGroupEndIndex")))?;
+    writer.write_event(Event::Text(BytesText::new(&span.end.to_string())))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionGroupEndIndex")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("wpml:actionGroupMode")))?;
+    writer.write_event(Event::Text(BytesText::new("sequence")))?;
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionGroupMode")))?;
+
+    write_action_trigger(writer, trigger)?;
+
+    write_take_photo_action(writer, 0, file_suffix, payload_position)?;
+
+    writer.write_event(Event::End(BytesEnd::new("wpml:actionGroup")))?;
+    Ok(())
+}
+
+/// Generates the waylines WPML, returning it with the number of waypoint placemarks
+/// written. Errors if that doesn't match the number of waypoints.
 pub fn generate_wpml(
     waypoints: &[Waypoint],
     heading_angle: &f64,
     drone: &Drone,
-) -> Result<String, Box<dyn std::error::Error>> {
+    config: &MissionConfig,
+    photo_offset: usize,
+) -> Result<(String, usize), Box<dyn std::error::Error>> {
+    let precision = &config.precision;
+    let payload_position = config.payload_position.to_string();
     let mut writer = Writer::new(Cursor::new(Vec::new()));
 
     // XML declaration
@@ -123,7 +510,9 @@ pub fn generate_wpml(
 
     // Required: Flight mode to first waypoint
     writer.write_event(Event::Start(BytesStart::new("wpml:flyToWaylineMode")))?;
-    writer.write_event(Event::Text(BytesText::new("safely")))?;
+    writer.write_event(Event::Text(BytesText::new(
+        config.fly_to_wayline_mode.as_wpml(),
+    )))?;
     writer.write_event(Event::End(BytesEnd::new("wpml:flyToWaylineMode")))?;
 
     // Required: Action after mission completion
@@ -133,12 +522,14 @@ pub fn generate_wpml(
 
     // Required: Behavior when RC is lost
     writer.write_event(Event::Start(BytesStart::new("wpml:exitOnRCLost")))?;
-    writer.write_event(Event::Text(BytesText::new("executeLostAction")))?;
+    writer.write_event(Event::Text(BytesText::new(
+        config.exit_on_rc_lost.as_wpml(),
+    )))?;
     writer.write_event(Event::End(BytesEnd::new("wpml:exitOnRCLost")))?;
 
     // Required: RC lost action type
     writer.write_event(Event::Start(BytesStart::new("wpml:executeRCLostAction")))?;
-    writer.write_event(Event::Text(BytesText::new("goBack")))?;
+    writer.write_event(Event::Text(BytesText::new(config.rc_lost_action.as_wpml())))?;
     writer.write_event(Event::End(BytesEnd::new("wpml:executeRCLostAction")))?;
 
     // Required: Safe takeoff height
@@ -146,6 +537,23 @@ pub fn generate_wpml(
     writer.write_event(Event::Text(BytesText::new("20")))?;
     writer.write_event(Event::End(BytesEnd::new("wpml:takeOffSecurityHeight")))?;
 
+    // Takeoff reference point, needed for heights relative to the start point
+    if let (HeightMode::RelativeToStartPoint, Some(home)) = (config.height_mode, config.home_point)
+    {
+        writer.write_event(Event::Start(BytesStart::new("wpml:takeOffRefPoint")))?;
+        let ref_point = format!(
+            "{:.*},{:.*},{:.*}",
+            precision.coordinates,
+            home[1],
+            precision.coordinates,
+            home[0],
+            precision.altitude,
+            home[2]
+        );
+        writer.write_event(Event::Text(BytesText::new(&ref_point)))?;
+        writer.write_event(Event::End(BytesEnd::new("wpml:takeOffRefPoint")))?;
+    }
+
     // Required: Global transitional speed
     writer.write_event(Event::Start(BytesStart::new(
         "wpml:globalTransitionalSpeed",
@@ -174,213 +582,466 @@ pub fn generate_wpml(
     writer.write_event(Event::Text(BytesText::new("52")))?; // M30 camera
     writer.write_event(Event::End(BytesEnd::new("wpml:payloadEnumValue")))?;
     writer.write_event(Event::Start(BytesStart::new("wpml:payloadPositionIndex")))?;
-    writer.write_event(Event::Text(BytesText::new("0")))?;
+    writer.write_event(Event::Text(BytesText::new(&payload_position)))?;
     writer.write_event(Event::End(BytesEnd::new("wpml:payloadPositionIndex")))?;
     writer.write_event(Event::End(BytesEnd::new("wpml:payloadInfo")))?;
 
     writer.write_event(Event::End(BytesEnd::new("wpml:missionConfig")))?;
 
-    // Folder for waypoints with required fields
-    writer.write_event(Event::Start(BytesStart::new("Folder")))?;
-
-    // Required: Template ID
-    writer.write_event(Event::Start(BytesStart::new("wpml:templateId")))?;
-    writer.write_event(Event::Text(BytesText::new("0")))?;
-    writer.write_event(Event::End(BytesEnd::new("wpml:templateId")))?;
-
-    // Required: Execute height mode
-    writer.write_event(Event::Start(BytesStart::new("wpml:executeHeightMode")))?;
-    writer.write_event(Event::Text(BytesText::new("WGS84")))?;
-    writer.write_event(Event::End(BytesEnd::new("wpml:executeHeightMode")))?;
-
-    // Required: Wayline ID
-    writer.write_event(Event::Start(BytesStart::new("wpml:waylineId")))?;
-    writer.write_event(Event::Text(BytesText::new("0")))?;
-    writer.write_event(Event::End(BytesEnd::new("wpml:waylineId")))?;
-
-    // Required: Auto flight speed
-    writer.write_event(Event::Start(BytesStart::new("wpml:autoFlightSpeed")))?;
-    writer.write_event(Event::Text(BytesText::new(&drone.speed.to_string())))?;
-    writer.write_event(Event::End(BytesEnd::new("wpml:autoFlightSpeed")))?;
-
-    // Gimbal pitch transition between waypoints
-    writer.write_event(Event::Start(BytesStart::new("wpml:gimbalPitchMode")))?;
-    writer.write_event(Event::Text(BytesText::new("usePointSetting")))?;
-    writer.write_event(Event::End(BytesEnd::new("wpml:gimbalPitchMode")))?;
-
-    // Write waypoints
-    for (i, waypoint) in waypoints.iter().enumerate() {
-        // Placemark for each waypoint
-        writer.write_event(Event::Start(BytesStart::new("Placemark")))?;
-
-        // Point geometry with proper coordinate format
-        writer.write_event(Event::Start(BytesStart::new("Point")))?;
-        writer.write_event(Event::Start(BytesStart::new("coordinates")))?;
-        let coords = format!("{:.8},{:.8}", waypoint.position[0], waypoint.position[1]);
-        writer.write_event(Event::Text(BytesText::new(&coords)))?;
-        writer.write_event(Event::End(BytesEnd::new("coordinates")))?;
-        writer.write_event(Event::End(BytesEnd::new("Point")))?;
-
-        // Required: Waypoint index
-        writer.write_event(Event::Start(BytesStart::new("wpml:index")))?;
-        writer.write_event(Event::Text(BytesText::new(&i.to_string())))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:index")))?;
-
-        // Required: Execute height
-        writer.write_event(Event::Start(BytesStart::new("wpml:executeHeight")))?;
-        writer.write_event(Event::Text(BytesText::new(&waypoint.altitude.to_string())))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:executeHeight")))?;
-
-        // Required: Waypoint speed
-        writer.write_event(Event::Start(BytesStart::new("wpml:waypointSpeed")))?;
-        writer.write_event(Event::Text(BytesText::new(&drone.speed.to_string())))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:waypointSpeed")))?;
-
-        // Required: Waypoint heading parameters
-        writer.write_event(Event::Start(BytesStart::new("wpml:waypointHeadingParam")))?;
-        writer.write_event(Event::Start(BytesStart::new("wpml:waypointHeadingMode")))?;
-        writer.write_event(Event::Text(BytesText::new("fixed")))?; // Keeps it facing one direction
-        writer.write_event(Event::End(BytesEnd::new("wpml:waypointHeadingMode")))?;
-        writer.write_event(Event::Start(BytesStart::new("wpml:waypointHeadingAngle")))?;
-        writer.write_event(Event::Text(BytesText::new(&heading_angle.to_string())))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:waypointHeadingAngle")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:waypointHeadingParam")))?;
-
-        // Required: Waypoint turn parameters
-        writer.write_event(Event::Start(BytesStart::new("wpml:waypointTurnParam")))?;
-        writer.write_event(Event::Start(BytesStart::new("wpml:waypointTurnMode")))?;
-        writer.write_event(Event::Text(BytesText::new(
-            "toPointAndStopWithDiscontinuityCurvature",
-        )))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:waypointTurnMode")))?;
-        writer.write_event(Event::Start(BytesStart::new(
-            "wpml:waypointTurnDampingDist",
-        )))?;
-        writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:waypointTurnDampingDist")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:waypointTurnParam")))?;
-
-        // Start action group
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionGroup")))?;
-
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionGroupStartIndex")))?;
-        writer.write_event(Event::Text(BytesText::new(&i.to_string())))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionGroupStartIndex")))?;
-
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionGroupEndIndex")))?;
-        writer.write_event(Event::Text(BytesText::new(&i.to_string())))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionGroupEndIndex")))?;
-
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionGroupMode")))?;
-        writer.write_event(Event::Text(BytesText::new("sequence")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionGroupMode")))?;
-
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionTrigger")))?;
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionTriggerType")))?;
-        writer.write_event(Event::Text(BytesText::new("reachPoint")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionTriggerType")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionTrigger")))?;
-
-        // Gimbal rotate action
-        writer.write_event(Event::Start(BytesStart::new("wpml:action")))?;
-
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionId")))?;
-        writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionId")))?;
-
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionActuatorFunc")))?;
-        writer.write_event(Event::Text(BytesText::new("gimbalRotate")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFunc")))?;
-
-        writer.write_event(Event::Start(BytesStart::new(
-            "wpml:actionActuatorFuncParam",
-        )))?;
-
-        writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRotateMode")))?;
-        writer.write_event(Event::Text(BytesText::new("absoluteAngle")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRotateMode")))?;
-
-        // Pitch control
-        writer.write_event(Event::Start(BytesStart::new(
-            "wpml:gimbalPitchRotateEnable",
-        )))?;
-        writer.write_event(Event::Text(BytesText::new("1")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalPitchRotateEnable")))?;
-        writer.write_event(Event::Start(BytesStart::new("wpml:gimbalPitchRotateAngle")))?;
-        writer.write_event(Event::Text(BytesText::new(&waypoint.bearing.to_string())))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalPitchRotateAngle")))?;
-
-        // Roll control
-        writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRollRotateEnable")))?;
-        writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRollRotateEnable")))?;
-        writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRollRotateAngle")))?;
-        writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRollRotateAngle")))?;
+    // Damping distance for each waypoint, scaled to the legs either side of it
+    let damping: Vec<f64> = match config.turn_damping_fraction {
+        Some(fraction) if config.turn_mode.is_curved() => {
+            let legs = get_leg_distances(waypoints);
+            let fraction = fraction.clamp(0.0, 0.5);
+            (0..waypoints.len())
+                .map(|i| {
+                    let before = i.checked_sub(1).and_then(|j| legs.get(j));
+                    let after = legs.get(i);
+                    let nearest = before.into_iter().chain(after).copied().reduce(f64::min);
+                    nearest.map_or(0.0, |distance| distance * fraction)
+                })
+                .collect()
+        }
+        _ => vec![0.0; waypoints.len()],
+    };
 
-        // Yaw control
-        writer.write_event(Event::Start(BytesStart::new("wpml:gimbalYawRotateEnable")))?;
-        writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalYawRotateEnable")))?;
-        writer.write_event(Event::Start(BytesStart::new("wpml:gimbalYawRotateAngle")))?;
-        writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalYawRotateAngle")))?;
+    let gimbal_pitches = get_gimbal_pitches(waypoints, config.gimbal_ramp_waypoints);
+
+    // Photo numbers carry on from previous segments so files don't collide on the SD card
+    let get_file_suffix = |i: usize| {
+        let photo_index = if config.continuous_numbering {
+            photo_offset + i
+        } else {
+            i
+        };
+        match &config.file_suffix_pattern {
+            Some(pattern) => format_file_suffix(pattern, photo_index),
+            None => photo_index.to_string(),
+        }
+    };
+    // Repeating triggers only take photos, over each run of photo waypoints, so the
+    // other actions aren't repeated along the way
+    let repeating = config.action_trigger.param().is_some();
+
+    // One wayline for the whole mission, or one for each flight line so a line can be
+    // re-flown on its own. Waypoint indices restart at 0 in each wayline.
+    let waylines: Vec<(usize, &[Waypoint])> = if config.wayline_per_line {
+        let mut start = 0;
+        waypoints
+            .chunk_by(|a, b| a.line == b.line)
+            .map(|line| {
+                let wayline = (start, line);
+                start += line.len();
+                wayline
+            })
+            .collect()
+    } else {
+        vec![(0, waypoints)]
+    };
 
-        writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRotateTimeEnable")))?;
-        writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRotateTimeEnable")))?;
-        writer.write_event(Event::Start(BytesStart::new("wpml:gimbalRotateTime")))?;
-        writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalRotateTime")))?;
+    for (wayline_id, (start, wayline)) in waylines.into_iter().enumerate() {
+        // Folder for waypoints with required fields
+        writer.write_event(Event::Start(BytesStart::new("Folder")))?;
 
-        writer.write_event(Event::Start(BytesStart::new("wpml:payloadPositionIndex")))?;
+        // Required: Template ID
+        writer.write_event(Event::Start(BytesStart::new("wpml:templateId")))?;
         writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:payloadPositionIndex")))?;
-
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFuncParam")))?;
+        writer.write_event(Event::End(BytesEnd::new("wpml:templateId")))?;
 
-        writer.write_event(Event::End(BytesEnd::new("wpml:action")))?;
+        // Required: Execute height mode
+        writer.write_event(Event::Start(BytesStart::new("wpml:executeHeightMode")))?;
+        writer.write_event(Event::Text(BytesText::new(config.height_mode.as_wpml())))?;
+        writer.write_event(Event::End(BytesEnd::new("wpml:executeHeightMode")))?;
 
-        // Take photo action
-        writer.write_event(Event::Start(BytesStart::new("wpml:action")))?;
+        // Required: Wayline ID
+        writer.write_event(Event::Start(BytesStart::new("wpml:waylineId")))?;
+        writer.write_event(Event::Text(BytesText::new(&wayline_id.to_string())))?;
+        writer.write_event(Event::End(BytesEnd::new("wpml:waylineId")))?;
 
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionId")))?;
-        writer.write_event(Event::Text(BytesText::new("1")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionId")))?;
-
-        writer.write_event(Event::Start(BytesStart::new("wpml:actionActuatorFunc")))?;
-        writer.write_event(Event::Text(BytesText::new("takePhoto")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFunc")))?;
+        // Required: Auto flight speed
+        writer.write_event(Event::Start(BytesStart::new("wpml:autoFlightSpeed")))?;
+        writer.write_event(Event::Text(BytesText::new(&drone.speed.to_string())))?;
+        writer.write_event(Event::End(BytesEnd::new("wpml:autoFlightSpeed")))?;
 
-        writer.write_event(Event::Start(BytesStart::new(
-            "wpml:actionActuatorFuncParam",
+        // Gimbal pitch transition between waypoints
+        writer.write_event(Event::Start(BytesStart::new("wpml:gimbalPitchMode")))?;
+        writer.write_event(Event::Text(BytesText::new(
+            config.gimbal_pitch_mode.as_wpml(),
         )))?;
+        writer.write_event(Event::End(BytesEnd::new("wpml:gimbalPitchMode")))?;
+
+        // Write waypoints
+        for (index, waypoint) in wayline.iter().enumerate() {
+            let i = start + index;
+            // Placemark for each waypoint
+            writer.write_event(Event::Start(BytesStart::new("Placemark")))?;
+
+            // Point geometry with proper coordinate format
+            writer.write_event(Event::Start(BytesStart::new("Point")))?;
+            writer.write_event(Event::Start(BytesStart::new("coordinates")))?;
+            let coords = format!(
+                "{:.*},{:.*}",
+                precision.coordinates,
+                waypoint.position[0],
+                precision.coordinates,
+                waypoint.position[1]
+            );
+            writer.write_event(Event::Text(BytesText::new(&coords)))?;
+            writer.write_event(Event::End(BytesEnd::new("coordinates")))?;
+            writer.write_event(Event::End(BytesEnd::new("Point")))?;
+
+            // Required: Waypoint index
+            writer.write_event(Event::Start(BytesStart::new("wpml:index")))?;
+            writer.write_event(Event::Text(BytesText::new(&index.to_string())))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:index")))?;
+
+            // Required: Execute height
+            writer.write_event(Event::Start(BytesStart::new("wpml:executeHeight")))?;
+            let height = format!("{:.*}", precision.altitude, waypoint.altitude);
+            writer.write_event(Event::Text(BytesText::new(&height)))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:executeHeight")))?;
+
+            // Required: Waypoint speed
+            writer.write_event(Event::Start(BytesStart::new("wpml:waypointSpeed")))?;
+            let speed = waypoint.speed.unwrap_or(drone.speed);
+            writer.write_event(Event::Text(BytesText::new(&speed.to_string())))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:waypointSpeed")))?;
+
+            // Required: Waypoint heading parameters
+            writer.write_event(Event::Start(BytesStart::new("wpml:waypointHeadingParam")))?;
+            let (heading_mode, heading) = match waypoint.heading {
+                // Turn smoothly to the waypoint's own heading
+                Some(heading) => ("smoothTransition", get_dji_heading(heading)),
+                // Keeps it facing along the flight lines, which are a math angle
+                None => (
+                    "fixed",
+                    get_dji_heading(math_angle_to_compass_bearing(*heading_angle)),
+                ),
+            };
+            writer.write_event(Event::Start(BytesStart::new("wpml:waypointHeadingMode")))?;
+            writer.write_event(Event::Text(BytesText::new(heading_mode)))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:waypointHeadingMode")))?;
+            writer.write_event(Event::Start(BytesStart::new("wpml:waypointHeadingAngle")))?;
+            writer.write_event(Event::Text(BytesText::new(&heading)))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:waypointHeadingAngle")))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:waypointHeadingParam")))?;
+
+            // Required: Waypoint turn parameters
+            writer.write_event(Event::Start(BytesStart::new("wpml:waypointTurnParam")))?;
+            writer.write_event(Event::Start(BytesStart::new("wpml:waypointTurnMode")))?;
+            writer.write_event(Event::Text(BytesText::new(config.turn_mode.as_wpml())))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:waypointTurnMode")))?;
+            writer.write_event(Event::Start(BytesStart::new(
+                "wpml:waypointTurnDampingDist",
+            )))?;
+            let damping_dist = format!("{:.*}", precision.altitude, damping[i]);
+            writer.write_event(Event::Text(BytesText::new(&damping_dist)))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:waypointTurnDampingDist")))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:waypointTurnParam")))?;
+
+            // Start action group
+            writer.write_event(Event::Start(BytesStart::new("wpml:actionGroup")))?;
+
+            writer.write_event(Event::Start(BytesStart::new("wpml:actionGroupStartIndex")))?;
+            writer.write_event(Event::Text(BytesText::new(&index.to_string())))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:actionGroupStartIndex")))?;
+
+            // Spreading the actions over the next leg needs a next waypoint
+            let trigger = match config.action_trigger {
+                ActionTrigger::BetweenAdjacentPoints if index + 1 == wayline.len() => {
+                    ActionTrigger::ReachPoint
+                }
+                _ if repeating => ActionTrigger::ReachPoint,
+                trigger => trigger,
+            };
+            let end_index = if trigger == ActionTrigger::BetweenAdjacentPoints {
+                index + 1
+            } else {
+                index
+            };
+            writer.write_event(Event::Start(BytesStart::new("wpml:actionGroupEndIndex")))?;
+            writer.write_event(Event::Text(BytesText::new(&end_index.to_string())))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:actionGroupEndIndex")))?;
+
+            writer.write_event(Event::Start(BytesStart::new("wpml:actionGroupMode")))?;
+            writer.write_event(Event::Text(BytesText::new("sequence")))?;
+            writer.write_event(Event::End(BytesEnd::new("wpml:actionGroupMode")))?;
+
+            write_action_trigger(&mut writer, &trigger)?;
+
+            let mut action_id = 0;
+
+            // Zoom action, only written at waypoints that change the zoom
+            if let Some(zoom) = waypoint.zoom {
+                writer.write_event(Event::Start(BytesStart::new("wpml:action")))?;
+
+                writer.write_event(Event::Start(BytesStart::new("wpml:actionId")))?;
+                writer.write_event(Event::Text(BytesText::new(&action_id.to_string())))?;
+                writer.write_event(Event::End(BytesEnd::new("wpml:actionId")))?;
+                action_id += 1;
+
+                writer.write_event(Event::Start(BytesStart::new("wpml:actionActuatorFunc")))?;
+                writer.write_event(Event::Text(BytesText::new("zoom")))?;
+                writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFunc")))?;
+
+                writer.write_event(Event::Start(BytesStart::new(
+                    "wpml:actionActuatorFuncParam",
+                )))?;
+
+                let focal_length = zoom * ZOOM_BASE_FOCAL_LENGTH;
+                writer.write_event(Event::Start(BytesStart::new("wpml:focalLength")))?;
+                writer.write_event(Event::Text(BytesText::new(&focal_length.to_string())))?;
+                writer.write_event(Event::End(BytesEnd::new("wpml:focalLength")))?;
+
+                writer.write_event(Event::Start(BytesStart::new("wpml:payloadPositionIndex")))?;
+                writer.write_event(Event::Text(BytesText::new(&payload_position)))?;
+                writer.write_event(Event::End(BytesEnd::new("wpml:payloadPositionIndex")))?;
+
+                writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFuncParam")))?;
+
+                writer.write_event(Event::End(BytesEnd::new("wpml:action")))?;
+            }
+
+            // Gimbal rotate action
+            write_gimbal_rotate_action(
+                &mut writer,
+                action_id,
+                gimbal_pitches[i],
+                &payload_position,
+            )?;
+            action_id += 1;
+
+            // Hover action, letting the aircraft settle before the photo
+            if waypoint.capture && config.hover_time > 0.0 {
+                writer.write_event(Event::Start(BytesStart::new("wpml:action")))?;
+
+                writer.write_event(Event::Start(BytesStart::new("wpml:actionId")))?;
+                writer.write_event(Event::Text(BytesText::new(&action_id.to_string())))?;
+                writer.write_event(Event::End(BytesEnd::new("wpml:actionId")))?;
+                action_id += 1;
+
+                writer.write_event(Event::Start(BytesStart::new("wpml:actionActuatorFunc")))?;
+                writer.write_event(Event::Text(BytesText::new("hover")))?;
+                writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFunc")))?;
+
+                writer.write_event(Event::Start(BytesStart::new(
+                    "wpml:actionActuatorFuncParam",
+                )))?;
+                writer.write_event(Event::Start(BytesStart::new("wpml:hoverTime")))?;
+                writer.write_event(Event::Text(BytesText::new(&config.hover_time.to_string())))?;
+                writer.write_event(Event::End(BytesEnd::new("wpml:hoverTime")))?;
+                writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFuncParam")))?;
+
+                writer.write_event(Event::End(BytesEnd::new("wpml:action")))?;
+            }
+
+            // Take photo action, skipped on transit waypoints, in timelapse mode and
+            // with a repeating trigger
+            if waypoint.capture && config.capture_mode == CaptureMode::Photo && !repeating {
+                let file_suffix = get_file_suffix(i);
+                write_take_photo_action(&mut writer, action_id, &file_suffix, &payload_position)?;
+                action_id += 1;
+
+                // Second gimbal rotation and photo for the oblique view from the same point
+                if let Some(pitch) = config.oblique_pitch {
+                    write_gimbal_rotate_action(&mut writer, action_id, pitch, &payload_position)?;
+                    action_id += 1;
+                    let oblique_suffix = format!("{}_oblique", file_suffix);
+                    write_take_photo_action(
+                        &mut writer,
+                        action_id,
+                        &oblique_suffix,
+                        &payload_position,
+                    )?;
+                }
+            }
+
+            writer.write_event(Event::End(BytesEnd::new("wpml:actionGroup")))?;
+
+            // Repeated photos from the first to the last of this run of photo waypoints
+            let run_start = waypoint.capture && (index == 0 || !wayline[index - 1].capture);
+            if repeating && run_start && config.capture_mode == CaptureMode::Photo {
+                let end = wayline[index..]
+                    .iter()
+                    .position(|w| !w.capture)
+                    .map_or(wayline.len() - 1, |offset| index + offset - 1);
+                write_interval_photo_group(
+                    &mut writer,
+                    index..end,
+                    &config.action_trigger,
+                    &get_file_suffix(i),
+                    &payload_position,
+                )?;
+            }
+
+            // Interval photos running from here to the stop mark
+            if let (CaptureMode::Timelapse { interval_s }, Some(TimelapseMark::Start)) =
+                (config.capture_mode, waypoint.timelapse)
+            {
+                let end = wayline[index + 1..]
+                    .iter()
+                    .position(|w| w.timelapse == Some(TimelapseMark::Stop))
+                    .map_or(wayline.len() - 1, |offset| index + 1 + offset);
+                write_interval_photo_group(
+                    &mut writer,
+                    index..end,
+                    &ActionTrigger::MultipleTiming { interval_s },
+                    "timelapse",
+                    &payload_position,
+                )?;
+            }
+
+            writer.write_event(Event::End(BytesEnd::new("Placemark")))?;
+        }
 
-        writer.write_event(Event::Start(BytesStart::new("wpml:fileSuffix")))?;
-        writer.write_event(Event::Text(BytesText::new(&i.to_string())))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:fileSuffix")))?;
+        // Close folder
+        writer.write_event(Event::End(BytesEnd::new("Folder")))?;
+    }
 
-        writer.write_event(Event::Start(BytesStart::new("wpml:payloadPositionIndex")))?;
-        writer.write_event(Event::Text(BytesText::new("0")))?;
-        writer.write_event(Event::End(BytesEnd::new("wpml:payloadPositionIndex")))?;
+    // Close document and kml
+    writer.write_event(Event::End(BytesEnd::new("Document")))?;
+    writer.write_event(Event::End(BytesEnd::new("kml")))?;
 
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionActuatorFuncParam")))?;
+    // Get the written XML as a string
+    let result = String::from_utf8(writer.into_inner().into_inner())?;
+
+    // Count the placemarks in the finished document, so a mismatch means waypoints
+    // were dropped or duplicated on the way out
+    let placemarks = count_placemarks(&result)?;
+    if placemarks != waypoints.len() {
+        return Err(format!(
+            "Wrote {} waypoint placemarks for {} waypoints",
+            placemarks,
+            waypoints.len()
+        )
+        .into());
+    }
+    Ok((result, placemarks))
+}
 
-        writer.write_event(Event::End(BytesEnd::new("wpml:action")))?;
+/// Returns the number of Placemark elements in the XML document
+fn count_placemarks(xml: &str) -> Result<usize, quick_xml::Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut placemarks = 0;
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) if element.name().as_ref() == b"Placemark" => placemarks += 1,
+            Event::Eof => return Ok(placemarks),
+            _ => {}
+        }
+    }
+}
 
-        writer.write_event(Event::End(BytesEnd::new("wpml:actionGroup")))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GimbalPitchMode;
+    use serde_json::json;
+    use std::io::Read;
+
+    fn drone() -> Drone {
+        serde_json::from_value(json!({
+            "model": "M30T",
+            "fov": 84.0,
+            "altitude": 100.0,
+            "overlap": 70.0,
+            "speed": 10.0,
+        }))
+        .unwrap()
+    }
 
-        writer.write_event(Event::End(BytesEnd::new("Placemark")))?;
+    /// Photo waypoints along a line just north of Christchurch
+    fn waypoints(count: usize) -> Vec<Waypoint> {
+        (0..count)
+            .map(|i| {
+                let position = [172.6, -43.5 + i as f64 * 0.0005];
+                let corners = [position; 5];
+                serde_json::from_value(json!({
+                    "coverage_rect": { "coords": corners, "center": position },
+                    "position": position,
+                    "bearing": 0.0,
+                    "altitude": 100.0,
+                }))
+                .unwrap()
+            })
+            .collect()
     }
 
-    // Close folder
-    writer.write_event(Event::End(BytesEnd::new("Folder")))?;
+    #[tokio::test]
+    async fn kmz_opens_with_each_compression() {
+        let waypoints = waypoints(20);
+        for (compression, method, name) in [
+            (
+                Compression::Deflated,
+                CompressionMethod::Deflated,
+                "deflated",
+            ),
+            (Compression::Stored, CompressionMethod::Stored, "stored"),
+        ] {
+            let config = MissionConfig {
+                compression,
+                ..Default::default()
+            };
+            let path = std::env::temp_dir().join(format!("uavsar_{}.kmz", name));
+            let path = path.to_str().unwrap();
+            let (_, placemarks) = create_kmz(&waypoints, &0.0, &drone(), &config, path, 0)
+                .await
+                .unwrap();
+            assert_eq!(placemarks, waypoints.len());
+
+            let mut archive = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+            let mut wpml = String::new();
+            {
+                let mut flightplan = archive.by_name("flightplan.wpml").unwrap();
+                assert_eq!(flightplan.compression(), method);
+                flightplan.read_to_string(&mut wpml).unwrap();
+            }
+            assert_eq!(count_placemarks(&wpml).unwrap(), waypoints.len());
+            assert_eq!(
+                archive.by_name("template.kml").unwrap().compression(),
+                method
+            );
+            fs::remove_file(path).unwrap();
+        }
+    }
 
-    // Close document and kml
-    writer.write_event(Event::End(BytesEnd::new("Document")))?;
-    writer.write_event(Event::End(BytesEnd::new("kml")))?;
+    /// Returns the text of every element with the given name in the XML document
+    fn element_texts(xml: &str, name: &str) -> Vec<String> {
+        let mut reader = Reader::from_str(xml);
+        let mut texts = Vec::new();
+        let mut inside = false;
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(element) => inside = element.name().as_ref() == name.as_bytes(),
+                Event::Text(text) if inside => texts.push(text.decode().unwrap().into_owned()),
+                Event::End(_) => inside = false,
+                Event::Eof => return texts,
+                _ => {}
+            }
+        }
+    }
 
-    // Get the written XML as a string
-    let result = writer.into_inner().into_inner();
-    Ok(String::from_utf8(result)?)
+    #[test]
+    fn gimbal_pitch_comes_from_pitch_not_bearing() {
+        let waypoints: Vec<Waypoint> = waypoints(3)
+            .into_iter()
+            .map(|w| Waypoint {
+                bearing: 45.0,
+                gimbal_pitch: -60.0,
+                ..w
+            })
+            .collect();
+        let config = MissionConfig {
+            gimbal_pitch_mode: GimbalPitchMode::Manual,
+            ..Default::default()
+        };
+        let (wpml, _) = generate_wpml(&waypoints, &0.0, &drone(), &config, 0).unwrap();
+
+        let pitches = element_texts(&wpml, "wpml:gimbalPitchRotateAngle");
+        assert_eq!(pitches.len(), waypoints.len());
+        assert!(pitches.iter().all(|pitch| pitch == "-60"));
+        let modes = element_texts(&wpml, "wpml:gimbalPitchMode");
+        assert!(!modes.is_empty());
+        assert!(modes.iter().all(|mode| mode == "manual"));
+    }
 }